@@ -0,0 +1,59 @@
+use crate::error::Error;
+use std::io;
+use wl_clipboard_rs::copy::{MimeType, Options, Source};
+
+/// Offer `data` on the Wayland clipboard as `mime_type`
+///
+/// Wayland clipboard contents are served by the process that set them, so,
+/// like `wl-copy`, the offer is served from a forked child that lives until
+/// something else is copied. This returns as soon as the offer is made.
+pub fn copy(data: Vec<u8>, mime_type: &str) -> Result<(), Error> {
+    let mut options = Options::new();
+    options.foreground(true);
+    let prepared = options
+        .prepare_copy(
+            Source::Bytes(data.into_boxed_slice()),
+            MimeType::Specific(mime_type.to_owned()),
+        )
+        .map_err(|err| Error::Clipboard(err.into()))?;
+
+    // SAFETY: we may have other threads, such as the directory watcher of
+    // `watch --copy`, and only the forking thread exists in the child. The
+    // child never returns into the async runtime or touches anything those
+    // threads use: it only serves the offer over the Wayland connection it
+    // owns and leaves with `_exit`, which skips the stdio cleanup whose locks
+    // another thread may have held. Allocating is fine, as glibc resets its
+    // allocator locks across fork.
+    match unsafe { libc::fork() } {
+        -1 => Err(Error::Clipboard(io::Error::last_os_error().into())),
+        0 => {
+            detach();
+            let failed = prepared.serve().is_err();
+            // SAFETY: ends the child without running any cleanup
+            unsafe { libc::_exit(i32::from(failed)) }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Detach the forked child from the caller's standard streams and working
+/// directory, like `wl-copy` does
+///
+/// Otherwise `$(cosmic-screenshot copy-last)` or a pipe would wait for the
+/// child, and so for the clipboard to be replaced, before seeing end of file.
+fn detach() {
+    // SAFETY: plain system calls on NUL terminated strings and descriptors
+    // this function owns
+    unsafe {
+        let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+        if null >= 0 {
+            for fd in 0..=2 {
+                libc::dup2(null, fd);
+            }
+            if null > 2 {
+                libc::close(null);
+            }
+        }
+        libc::chdir(c"/".as_ptr());
+    }
+}
@@ -0,0 +1,42 @@
+use clap::ValueEnum;
+use image::{imageops, DynamicImage, RgbaImage};
+
+/// How combined screenshots are arranged relative to each other
+#[derive(ValueEnum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Place images side by side, left to right
+    #[default]
+    Horizontal,
+    /// Stack images on top of each other, top to bottom
+    Vertical,
+}
+
+/// Combine images into a single image in the given layout
+///
+/// Images are aligned to the top (horizontal) or left (vertical) edge, and
+/// any area not covered by an image is left transparent.
+pub fn compose(images: &[DynamicImage], layout: Layout) -> RgbaImage {
+    let (width, height) = match layout {
+        Layout::Horizontal => (
+            images.iter().map(DynamicImage::width).sum(),
+            images.iter().map(DynamicImage::height).max().unwrap_or(0),
+        ),
+        Layout::Vertical => (
+            images.iter().map(DynamicImage::width).max().unwrap_or(0),
+            images.iter().map(DynamicImage::height).sum(),
+        ),
+    };
+
+    let mut composed = RgbaImage::new(width, height);
+    let mut offset = 0;
+    for image in images {
+        let (x, y, advance) = match layout {
+            Layout::Horizontal => (offset, 0, image.width()),
+            Layout::Vertical => (0, offset, image.height()),
+        };
+        imageops::overlay(&mut composed, &image.to_rgba8(), x.into(), y.into());
+        offset += advance;
+    }
+
+    composed
+}
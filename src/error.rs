@@ -0,0 +1,262 @@
+use crate::portal;
+use ashpd::desktop::ResponseError;
+use std::{fmt, io, path::PathBuf, process::ExitCode};
+
+/// Errors that end a screenshot run without a screenshot
+///
+/// Each error maps to one of the documented exit codes:
+///
+/// | Code | Meaning                                      |
+/// |------|----------------------------------------------|
+/// | 0    | Success                                      |
+/// | 1    | Generic failure                              |
+/// | 2    | The screenshot was cancelled or timed out    |
+/// | 3    | No display, session bus or screenshot portal |
+/// | 4    | Invalid command line arguments               |
+#[derive(Debug)]
+pub enum Error {
+    /// The command line arguments could not be parsed
+    Arguments(clap::Error),
+    /// The user cancelled the screenshot dialog
+    Cancelled,
+    /// The interactive screenshot was not completed within the given seconds
+    TimedOut(u64),
+    /// Neither a Wayland nor an X11 display is available
+    NoDisplay,
+    /// The screenshot portal is not running or lacks the Screenshot interface
+    PortalUnavailable(ashpd::Error),
+    /// Sending the screenshot request to the portal failed
+    Request(ashpd::Error),
+    /// The portal answered the screenshot request with an error
+    Response(ashpd::Error),
+    /// The portal returned the screenshot with a URI scheme we can't handle
+    UnsupportedScheme(String),
+    /// No directory to save screenshots to could be found
+    MissingSaveDirectory,
+    /// A file operation on the screenshot failed
+    Io(&'static str, io::Error),
+    /// Decoding or encoding the screenshot image failed
+    Image(image::ImageError),
+    /// No screenshot was found in the given directory
+    NoScreenshots(PathBuf),
+    /// Offering the screenshot on the clipboard failed
+    Clipboard(Box<dyn std::error::Error + Send + Sync>),
+    /// Sending the desktop notification failed
+    Notification(zbus::Error),
+    /// Watching a directory for new images failed
+    Watch(Box<dyn std::error::Error + Send + Sync>),
+    /// Serving the screenshot over HTTP failed
+    #[cfg(feature = "serve")]
+    Serve(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl Error {
+    /// Classify an error from sending the screenshot request
+    pub fn request(err: ashpd::Error) -> Self {
+        if portal::is_unavailable(&err) {
+            Self::PortalUnavailable(err)
+        } else {
+            Self::Request(err)
+        }
+    }
+
+    /// Classify an error response to the screenshot request
+    pub fn response(err: ashpd::Error) -> Self {
+        match err {
+            ashpd::Error::Response(ResponseError::Cancelled) => Self::Cancelled,
+            err => Self::Response(err),
+        }
+    }
+
+    /// The process exit code for this error
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Self::Cancelled | Self::TimedOut(_) => ExitCode::from(2),
+            Self::NoDisplay | Self::PortalUnavailable(_) => ExitCode::from(3),
+            Self::Arguments(_) => ExitCode::from(4),
+            _ => ExitCode::from(1),
+        }
+    }
+
+    /// A single line describing the failure, suitable for showing to users
+    ///
+    /// Unlike the `Display` output, this leaves out low level details such
+    /// as D-Bus error names where they don't help the user.
+    pub fn to_user_facing(&self) -> String {
+        match self {
+            Self::Arguments(err) => err.to_string().trim_end().to_owned(),
+            Self::Cancelled | Self::TimedOut(_) => {
+                let mut message = self.to_string();
+                message[..1].make_ascii_uppercase();
+                message
+            }
+            Self::PortalUnavailable(_) => String::from(
+                "Screenshot failed: the screenshot portal is not available; \
+                 xdg-desktop-portal and a backend implementing screenshots, \
+                 such as xdg-desktop-portal-cosmic, must be running",
+            ),
+            // only sent once the screenshot is saved
+            Self::Notification(_) => format!("Screenshot saved, but {self}"),
+            err => format!("Screenshot failed: {err}"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Arguments(err) => f.write_str(err.to_string().trim_end()),
+            Self::Cancelled => f.write_str("screenshot cancelled"),
+            Self::TimedOut(secs) => {
+                write!(
+                    f,
+                    "screenshot cancelled: not completed within {secs} seconds"
+                )
+            }
+            Self::NoDisplay => f.write_str(
+                "no display server detected: neither WAYLAND_DISPLAY nor DISPLAY is set",
+            ),
+            Self::PortalUnavailable(err) => {
+                write!(f, "the screenshot portal is not available: {err}")
+            }
+            Self::Request(err) => write!(f, "failed to send screenshot request: {err}"),
+            Self::Response(err) => write!(f, "failed to receive screenshot response: {err}"),
+            Self::UnsupportedScheme(scheme) => write!(f, "unsupported scheme '{scheme}'"),
+            Self::MissingSaveDirectory => f.write_str("failed to locate picture directory"),
+            Self::Io(context, err) => write!(f, "{context}: {err}"),
+            Self::Image(err) => write!(f, "failed to process screenshot image: {err}"),
+            Self::NoScreenshots(dir) => write!(f, "no screenshots found in {}", dir.display()),
+            Self::Clipboard(err) => write!(f, "failed to copy screenshot to clipboard: {err}"),
+            Self::Notification(err) => write!(f, "failed to send notification: {err}"),
+            Self::Watch(err) => write!(f, "failed to watch directory: {err}"),
+            #[cfg(feature = "serve")]
+            Self::Serve(err) => write!(f, "failed to serve screenshot: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Self {
+        Self::Image(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn portal_error() -> ashpd::Error {
+        ashpd::Error::Zbus(zbus::Error::InterfaceNotFound)
+    }
+
+    fn image_error() -> image::ImageError {
+        image::ImageError::IoError(io::Error::other("truncated"))
+    }
+
+    #[test]
+    fn exit_codes() {
+        let cases = [
+            (Error::Cancelled, 2),
+            (Error::TimedOut(30), 2),
+            (Error::NoDisplay, 3),
+            (Error::PortalUnavailable(portal_error()), 3),
+            (
+                Error::Arguments(clap::Error::new(clap::error::ErrorKind::InvalidValue)),
+                4,
+            ),
+            (Error::Request(portal_error()), 1),
+            (Error::Response(portal_error()), 1),
+            (Error::UnsupportedScheme(String::from("ftp")), 1),
+            (Error::MissingSaveDirectory, 1),
+            (Error::Io("failed", io::Error::other("broken")), 1),
+            (Error::NoScreenshots(PathBuf::from("/tmp")), 1),
+            (Error::Image(image_error()), 1),
+            (Error::Clipboard("no seat".into()), 1),
+            (Error::Notification(zbus::Error::InterfaceNotFound), 1),
+            (Error::Watch("no such directory".into()), 1),
+            #[cfg(feature = "serve")]
+            (Error::Serve("address in use".into()), 1),
+        ];
+        for (err, code) in cases {
+            assert_eq!(err.exit_code(), ExitCode::from(code), "{err:?}");
+        }
+    }
+
+    #[test]
+    fn user_facing_messages() {
+        let cases = [
+            (Error::Cancelled, "Screenshot cancelled"),
+            (
+                Error::TimedOut(30),
+                "Screenshot cancelled: not completed within 30 seconds",
+            ),
+            (
+                Error::NoDisplay,
+                "Screenshot failed: no display server detected: neither WAYLAND_DISPLAY nor DISPLAY is set",
+            ),
+            (
+                Error::PortalUnavailable(portal_error()),
+                "Screenshot failed: the screenshot portal is not available; \
+                 xdg-desktop-portal and a backend implementing screenshots, \
+                 such as xdg-desktop-portal-cosmic, must be running",
+            ),
+            (
+                Error::UnsupportedScheme(String::from("ftp")),
+                "Screenshot failed: unsupported scheme 'ftp'",
+            ),
+            (
+                Error::MissingSaveDirectory,
+                "Screenshot failed: failed to locate picture directory",
+            ),
+            (
+                Error::Io("failed to move screenshot", io::Error::other("broken")),
+                "Screenshot failed: failed to move screenshot: broken",
+            ),
+            (
+                Error::NoScreenshots(PathBuf::from("/tmp")),
+                "Screenshot failed: no screenshots found in /tmp",
+            ),
+            (
+                Error::Request(portal_error()),
+                "Screenshot failed: failed to send screenshot request: ZBus Error: Interface not found",
+            ),
+            (
+                Error::Response(portal_error()),
+                "Screenshot failed: failed to receive screenshot response: ZBus Error: Interface not found",
+            ),
+            (
+                Error::Image(image_error()),
+                "Screenshot failed: failed to process screenshot image: truncated",
+            ),
+            (
+                Error::Clipboard("no seat".into()),
+                "Screenshot failed: failed to copy screenshot to clipboard: no seat",
+            ),
+            (
+                Error::Notification(zbus::Error::InterfaceNotFound),
+                "Screenshot saved, but failed to send notification: Interface not found",
+            ),
+            (
+                Error::Watch("no such directory".into()),
+                "Screenshot failed: failed to watch directory: no such directory",
+            ),
+            #[cfg(feature = "serve")]
+            (
+                Error::Serve("address in use".into()),
+                "Screenshot failed: failed to serve screenshot: address in use",
+            ),
+        ];
+        for (err, message) in cases {
+            assert_eq!(err.to_user_facing(), message);
+        }
+    }
+
+    #[test]
+    fn argument_errors_are_shown_as_is() {
+        let err = clap::Error::raw(clap::error::ErrorKind::InvalidValue, "bad value\n");
+        let message = err.to_string().trim_end().to_owned();
+        assert_eq!(Error::Arguments(err).to_user_facing(), message);
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    io,
+    os::{fd::AsFd, unix::process::CommandExt},
+    process::{self, Stdio},
+    time::Duration,
+};
+use tokio::process::Command;
+
+/// How long the on-save command may run before it is killed
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Quote `value` as a single `sh` word
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// The `sh` command line for `command`, with `%f` replaced by the quoted path
+///
+/// If `command` has no `%f`, the path is appended as the last argument.
+fn command_line(command: &str, path: &str) -> String {
+    let path = shell_quote(path);
+    if command.contains("%f") {
+        command.replace("%f", &path)
+    } else {
+        format!("{command} {path}")
+    }
+}
+
+/// Run the on-save command for the screenshot saved at `path`
+///
+/// The command's output goes to stderr so stdout keeps only the saved path.
+/// Failures are reported as warnings, since the screenshot is already saved.
+pub async fn run(command: &str, path: &str) {
+    let stdout = io::stderr()
+        .as_fd()
+        .try_clone_to_owned()
+        .map_or_else(|_| Stdio::null(), Stdio::from);
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command_line(command, path))
+        .stdout(stdout)
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            eprintln!("failed to run on-save command: {err}");
+            return;
+        }
+    };
+
+    match tokio::time::timeout(TIMEOUT, child.wait()).await {
+        Ok(Ok(status)) if status.success() => {}
+        Ok(Ok(status)) => eprintln!("on-save command failed: {status}"),
+        Ok(Err(err)) => eprintln!("failed to run on-save command: {err}"),
+        Err(_) => eprintln!("on-save command killed after {} seconds", TIMEOUT.as_secs()),
+    }
+}
+
+/// Launch `command` on the screenshot saved at `path` without waiting for it
+///
+/// The program runs in its own process group with no standard streams, so it
+/// keeps running after we exit and can't write into our output.
+pub fn open_with(command: &str, path: &str) {
+    let child = process::Command::new("sh")
+        .arg("-c")
+        .arg(command_line(command, path))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn();
+    if let Err(err) = child {
+        eprintln!("failed to open screenshot with '{command}': {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The arguments `sh` passes to a command run by `command_line`
+    fn argv(command: &str, path: &str) -> Vec<String> {
+        let output = process::Command::new("sh")
+            .arg("-c")
+            .arg(command_line(&format!("printf '%s\\n' {command}"), path))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(shell_quote("plain"), "'plain'");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn path_is_appended_without_placeholder() {
+        assert_eq!(command_line("gimp", "/tmp/a b.png"), "gimp '/tmp/a b.png'");
+    }
+
+    #[test]
+    fn paths_reach_the_command_unchanged() {
+        for path in [
+            "/tmp/shot.png",
+            "/tmp/with space.png",
+            "/tmp/it's.png",
+            r#"/tmp/"double".png"#,
+            "/tmp/$HOME `id` *.png",
+        ] {
+            assert_eq!(argv("first", path), ["first", path], "{path}");
+            assert_eq!(argv("%f last", path), [path, "last"], "{path}");
+        }
+    }
+
+    #[test]
+    fn placeholder_inside_arguments() {
+        let path = "/tmp/a b.png";
+        assert_eq!(argv("--file=%f", path), ["--file=/tmp/a b.png"]);
+        assert_eq!(
+            argv("%f --copy=%f.bak", path),
+            [path, "--copy=/tmp/a b.png.bak"]
+        );
+    }
+}
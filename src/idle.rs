@@ -0,0 +1,78 @@
+use crate::{error::Error, portal};
+use image::RgbaImage;
+use std::{
+    fs,
+    time::{Duration, Instant},
+};
+
+/// Width and height probe frames are downscaled to before being compared
+const SAMPLE_SIZE: u32 = 64;
+/// Mean per-channel difference below which two probe frames count as unchanged
+const THRESHOLD: f64 = 1.0;
+/// Delay between probe captures
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Give up waiting for the screen to settle after this long
+const MAX_WAIT: Duration = Duration::from_secs(30);
+
+/// Take a non-interactive capture of the screen, downscaled for comparison
+async fn probe() -> Result<RgbaImage, Error> {
+    let response = portal::send_request(false, false)
+        .await
+        .map_err(Error::request)?
+        .response()
+        .map_err(Error::response)?;
+    let path = portal::screenshot_file(response.uri())?;
+    let image = image::open(&path)?;
+    fs::remove_file(&path)
+        .map_err(|err| Error::Io("failed to remove temporary screenshot", err))?;
+
+    Ok(image.thumbnail_exact(SAMPLE_SIZE, SAMPLE_SIZE).to_rgba8())
+}
+
+/// Mean absolute difference between the channels of two equally sized frames
+fn difference(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    let total: u64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(a, b)| u64::from(a.abs_diff(*b)))
+        .sum();
+    total as f64 / a.as_raw().len().max(1) as f64
+}
+
+/// Wait until the screen has not changed for `stable_for`
+///
+/// The screen is sampled every 200ms. Each sample is a full resolution
+/// non-interactive capture that is decoded and downscaled before comparing,
+/// so this keeps the portal and CPU busy while waiting. If the screen never
+/// settles, this gives up after 30 seconds so the screenshot is still taken.
+///
+/// Only call this if the portal supports non-interactive captures, or every
+/// sample opens a dialog.
+pub async fn wait_for_idle(stable_for: Duration) -> Result<(), Error> {
+    let start = Instant::now();
+    let mut stable_since = Instant::now();
+    let mut previous: Option<RgbaImage> = None;
+
+    loop {
+        let frame = probe().await?;
+        match previous {
+            Some(ref previous) if difference(previous, &frame) < THRESHOLD => {
+                if stable_since.elapsed() >= stable_for {
+                    return Ok(());
+                }
+            }
+            _ => stable_since = Instant::now(),
+        }
+        previous = Some(frame);
+
+        if start.elapsed() >= MAX_WAIT {
+            eprintln!(
+                "screen did not settle within {} seconds, taking screenshot anyway",
+                MAX_WAIT.as_secs()
+            );
+            return Ok(());
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
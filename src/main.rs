@@ -1,7 +1,34 @@
 use ashpd::desktop::screenshot::Screenshot;
-use clap::{command, ArgAction, Parser};
-use std::{collections::HashMap, fs, os::unix::fs::MetadataExt, path::PathBuf};
-use zbus::{dbus_proxy, zvariant::Value, Connection};
+use clap::{error::ErrorKind, ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use error::Error;
+use image::{DynamicImage, ImageFormat};
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+use tokio::signal::unix::{signal, SignalKind};
+
+mod clipboard;
+mod compose;
+mod error;
+mod hook;
+mod idle;
+mod metadata;
+mod naming;
+mod notification;
+mod portal;
+mod save;
+#[cfg(feature = "serve")]
+mod serve;
+mod sharpness;
+mod watch;
+mod watermark;
 
 #[derive(Parser, Default, Debug, Clone, PartialEq, Eq)]
 #[command(version, about, long_about = None)]
@@ -31,104 +58,1091 @@ struct Args {
         action = ArgAction::Set)]
     notify: bool,
     /// The directory to save the screenshot to, if not performing an interactive screenshot
-    #[clap(short, long)]
+    #[clap(short, long, global = true)]
     save_dir: Option<PathBuf>,
+    /// Create the save directory, or ~/Pictures, if it doesn't exist
+    #[clap(long, global = true)]
+    mkdir: bool,
+    /// The file name to save screenshots under, without the extension
+    ///
+    /// Takes strftime tokens such as %Y or %H, %o for the output name and %n
+    /// for a counter that increases until the name is unused.
+    #[clap(long, value_name = "TEMPLATE", default_value = naming::DEFAULT_TEMPLATE, global = true)]
+    name_template: String,
+    /// Allow --name-template to contain '/' to save into subdirectories
+    #[clap(long, global = true)]
+    allow_subdirs: bool,
+    /// The image format to save the screenshot in
+    #[clap(long, value_enum, default_value_t)]
+    format: save::Format,
+    /// Quality from 0 to 100 for lossy formats [default: 90]
+    #[clap(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    quality: Option<u8>,
+    /// Cancel an interactive screenshot that is not completed within this many seconds
+    ///
+    /// Only applies when --interactive is enabled; non-interactive captures
+    /// never wait for the user.
+    #[clap(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+    /// Wait this many seconds before taking the screenshot, e.g. 2.5
+    ///
+    /// The delay runs before the screenshot dialog opens, so there is time
+    /// to arrange windows; it cannot be applied after an interactive selection.
+    #[clap(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    delay: Option<Duration>,
+    /// Don't print a countdown to stderr during --delay
+    #[clap(long)]
+    no_countdown: bool,
+    /// Keep taking screenshots this many seconds apart until Ctrl+C is pressed
+    ///
+    /// Without %n in --name-template, a counter is appended to the file name
+    /// so the screenshots don't overwrite each other. Requires
+    /// --interactive=false.
+    #[clap(long,
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        conflicts_with = "stdout")]
+    interval: Option<Duration>,
+    /// Stop --interval after this many screenshots
+    #[clap(long,
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "interval")]
+    count: Option<u32>,
+    /// Wait until the screen has stopped changing for this many milliseconds
+    /// before taking the screenshot
+    ///
+    /// The screen is sampled with full resolution captures several times a
+    /// second. Requires a portal that supports non-interactive captures.
+    #[clap(long,
+        value_name = "MS",
+        num_args(0..=1),
+        require_equals(true),
+        default_missing_value("500"))]
+    wait_idle: Option<u64>,
+    /// Take this many screenshots in sequence and combine them into one image
+    #[clap(long,
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u32).range(2..))]
+    compose: Option<u32>,
+    /// How to arrange the screenshots combined with --compose or --append-to
+    #[clap(long, value_enum, default_value_t)]
+    compose_layout: compose::Layout,
+    /// Take this many screenshots in a row and keep only the sharpest
+    ///
+    /// Useful for animated interfaces, where a single capture may catch a
+    /// blurred or half-drawn frame. Requires --interactive=false.
+    #[clap(long,
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u32).range(2..),
+        conflicts_with = "compose")]
+    sharpest_of: Option<u32>,
+    /// Append the screenshot as a new tile to this contact sheet image
+    ///
+    /// The sheet is created by the first capture and saved in the format
+    /// matching its extension.
+    #[clap(long, value_name = "FILE")]
+    append_to: Option<PathBuf>,
+    /// Run a shell command after the screenshot is saved
+    ///
+    /// `%f` in the command is replaced with the saved path; without it the
+    /// path is appended. The command is killed after 30 seconds.
+    #[clap(long, value_name = "COMMAND")]
+    on_save: Option<String>,
+    /// Open the saved screenshot with this command, or xdg-open if none is given
+    ///
+    /// `%f` is replaced like in --on-save. The command is not waited for.
+    #[clap(long,
+        value_name = "COMMAND",
+        num_args(0..=1),
+        require_equals(true),
+        default_missing_value("xdg-open"))]
+    open_with: Option<String>,
+    /// Draw the capture time onto the screenshot
+    #[clap(long)]
+    timestamp: bool,
+    /// The corner --timestamp is drawn in
+    #[clap(long, value_enum, default_value_t)]
+    timestamp_corner: watermark::Corner,
+    /// Record the capture time, tool version and desktop in PNG screenshots
+    #[clap(long,
+        default_missing_value("true"),
+        default_value("true"),
+        num_args(0..=1),
+        require_equals(true),
+        action = ArgAction::Set)]
+    metadata: bool,
+    /// Write the screenshot to stdout instead of saving it, for piping into other tools
+    ///
+    /// The image is encoded in --format and the saved path is not printed.
+    #[clap(long)]
+    stdout: bool,
+    /// Serve the screenshot over HTTP on localhost, on the given port or a free one
+    #[cfg(feature = "serve")]
+    #[clap(long,
+        value_name = "PORT",
+        num_args(0..=1),
+        require_equals(true),
+        default_missing_value("0"),
+        conflicts_with = "interval")]
+    serve: Option<u16>,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[dbus_proxy(assume_defaults = true)]
-trait Notifications {
-    /// Call the org.freedesktop.Notifications.Notify D-Bus method
-    #[allow(clippy::too_many_arguments)]
-    fn notify(
-        &self,
-        app_name: &str,
-        replaces_id: u32,
-        app_icon: &str,
-        summary: &str,
-        body: &str,
-        actions: &[&str],
-        hints: HashMap<&str, &Value<'_>>,
-        expire_timeout: i32,
-    ) -> zbus::Result<u32>;
-}
-
-//TODO: better error handling
-#[tokio::main(flavor = "current_thread")]
-async fn main() {
-    let args = Args::parse();
-    let picture_dir = (!args.interactive).then(|| {
-        args.save_dir
-            .filter(|dir| dir.is_dir())
-            .unwrap_or_else(|| dirs::picture_dir().expect("failed to locate picture directory"))
-    });
-
-    let response = Screenshot::request()
-        .interactive(args.interactive)
-        .modal(args.modal)
-        .send()
-        .await
-        .expect("failed to send screenshot request")
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+enum Command {
+    /// Copy the most recent screenshot in the save directory to the clipboard
+    CopyLast,
+    /// Watch a directory and post-process images saved to it by other tools
+    ///
+    /// New images are handled like screenshots taken by this command: the
+    /// path is printed, --on-save runs and --notify applies.
+    Watch {
+        /// The directory to watch
+        dir: PathBuf,
+        /// Also copy each new image to the clipboard
+        #[clap(long)]
+        copy: bool,
+    },
+    /// Print a completion script for the given shell to stdout
+    #[command(hide = true)]
+    Completions {
+        /// The shell to generate completions for
+        shell: clap_complete::Shell,
+    },
+}
+
+/// Where a screenshot ended up
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Saved {
+    File(PathBuf),
+    Clipboard,
+    Stdout,
+}
+
+/// Returns true if a Wayland or X11 display is reachable from this session
+fn has_display() -> bool {
+    ["WAYLAND_DISPLAY", "DISPLAY"]
+        .iter()
+        .any(|var| env::var_os(var).is_some_and(|value| !value.is_empty()))
+}
+
+/// Parse a non-negative, possibly fractional, number of seconds
+fn parse_seconds(secs: &str) -> Result<Duration, String> {
+    let secs: f64 = secs
+        .parse()
+        .map_err(|err: std::num::ParseFloatError| err.to_string())?;
+    Duration::try_from_secs_f64(secs)
+        .map_err(|_| format!("{secs} is not a valid number of seconds"))
+}
+
+/// Sleep for `delay`, counting down the remaining whole seconds on `countdown`
+///
+/// Nothing is printed for delays under a second, including `--delay 0`.
+async fn delay(delay: Duration, countdown: Option<&mut dyn Write>) {
+    let Some(out) = countdown else {
+        tokio::time::sleep(delay).await;
+        return;
+    };
+
+    // sleep off the fraction first so the countdown ticks on whole seconds
+    let secs = delay.as_secs();
+    tokio::time::sleep(delay - Duration::from_secs(secs)).await;
+    if secs == 0 {
+        return;
+    }
+
+    _ = write!(out, "Taking screenshot in");
+    for remaining in (1..=secs).rev() {
+        _ = write!(out, " {remaining}…");
+        _ = out.flush();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    _ = writeln!(out);
+}
+
+/// The directories screenshots may be saved to, most preferred first, and
+/// whether each may be created by --mkdir
+///
+/// These are --save-dir, `$XDG_PICTURES_DIR`, `$XDG_SCREENSHOTS_DIR`, the
+/// XDG user-dirs pictures directory and ~/Pictures. Empty variables are
+/// treated as unset.
+fn save_dir_candidates(
+    save_dir: Option<&Path>,
+    var: impl Fn(&str) -> Option<OsString>,
+    user_picture_dir: Option<PathBuf>,
+    home_dir: Option<&Path>,
+) -> [(Option<PathBuf>, bool); 5] {
+    let env_dir = |name| {
+        var(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    };
+    [
+        (save_dir.map(Path::to_owned), true),
+        (env_dir("XDG_PICTURES_DIR"), false),
+        (env_dir("XDG_SCREENSHOTS_DIR"), false),
+        (user_picture_dir, false),
+        (home_dir.map(|home| home.join("Pictures")), true),
+    ]
+}
+
+/// The first existing directory of `candidates`, or the first creatable one
+/// that is missing, created, if `mkdir` is set
+fn first_save_dir(
+    candidates: impl IntoIterator<Item = (Option<PathBuf>, bool)>,
+    mkdir: bool,
+) -> Result<Option<PathBuf>, Error> {
+    for (dir, creatable) in candidates {
+        let Some(dir) = dir else {
+            continue;
+        };
+        if dir.is_dir() {
+            return Ok(Some(dir));
+        }
+        if creatable && mkdir {
+            fs::create_dir_all(&dir)
+                .map_err(|err| Error::Io("failed to create save directory", err))?;
+            return Ok(Some(dir));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The directory screenshots are saved to
+///
+/// The first existing directory of [`save_dir_candidates`] is used, falling
+/// back to the current directory. With --mkdir a missing --save-dir or
+/// ~/Pictures is created instead of skipped.
+fn save_dir(args: &Args) -> Result<PathBuf, Error> {
+    let candidates = save_dir_candidates(
+        args.save_dir.as_deref(),
+        |name| env::var_os(name),
+        dirs::picture_dir(),
+        dirs::home_dir().as_deref(),
+    );
+    match first_save_dir(candidates, args.mkdir)? {
+        Some(dir) => Ok(dir),
+        None => env::current_dir().map_err(|_| Error::MissingSaveDirectory),
+    }
+}
+
+/// The most recently modified screenshot in `dir`, and its image format
+///
+/// Only images named as `template` could have named them count, so other
+/// pictures in the same directory are skipped. Screenshots saved to
+/// subdirectories by the template are not found.
+fn latest_screenshot(dir: &Path, template: &str) -> Result<Option<(PathBuf, ImageFormat)>, Error> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| Error::Io("failed to read screenshot directory", err))?;
+    let latest = entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let path = entry.path();
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            naming::matches(template, &stem)
+        })
+        .filter_map(|entry| {
+            let format = ImageFormat::from_path(entry.path()).ok()?;
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            Some((modified, entry.path(), format))
+        })
+        .max_by_key(|(modified, ..)| *modified)
+        .map(|(_, path, format)| (path, format));
+
+    Ok(latest)
+}
+
+/// Copy the most recent screenshot in the save directory to the clipboard
+fn copy_last(args: &Args) -> Result<(), Error> {
+    let dir = save_dir(args)?;
+    let (path, format) =
+        latest_screenshot(&dir, &args.name_template)?.ok_or(Error::NoScreenshots(dir))?;
+    let data = fs::read(&path).map_err(|err| Error::Io("failed to read screenshot", err))?;
+    clipboard::copy(data, format.to_mime_type())?;
+
+    println!("{}", path.display());
+    Ok(())
+}
+
+/// The path in `dir` for a screenshot taken now, creating any subdirectories
+/// the name template asks for
+fn screenshot_path(args: &Args, dir: &Path) -> Result<PathBuf, Error> {
+    let path = naming::render(
+        &args.name_template,
+        dir,
+        &chrono::Local::now(),
+        args.format.extension(),
+    );
+    if let Some(parent) = path.parent().filter(|parent| *parent != dir) {
+        fs::create_dir_all(parent)
+            .map_err(|err| Error::Io("failed to create screenshot directory", err))?;
+    }
+
+    Ok(path)
+}
+
+/// The encoding quality for lossy formats
+fn quality(args: &Args) -> u8 {
+    args.quality.unwrap_or(save::DEFAULT_QUALITY)
+}
+
+/// Returns true if the portal's PNG has to be decoded and encoded again,
+/// rather than used as is
+fn needs_reencode(args: &Args) -> bool {
+    args.format != save::Format::Png || args.timestamp
+}
+
+/// Apply the requested changes to a captured image before it is saved
+fn prepare(args: &Args, image: DynamicImage) -> DynamicImage {
+    if !args.timestamp {
+        return image;
+    }
+
+    let mut image = image.into_rgba8();
+    let time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+    watermark::draw_text(&mut image, &time.to_string(), args.timestamp_corner);
+    DynamicImage::from(image)
+}
+
+/// Request a screenshot from the portal and wait for the user to complete it
+async fn request_screenshot(args: &Args) -> Result<Screenshot, Error> {
+    let request = portal::send_request(args.interactive, args.modal);
+    let request = match args.timeout.filter(|_| args.interactive) {
+        Some(secs) => tokio::time::timeout(Duration::from_secs(secs), request)
+            .await
+            .map_err(|_| Error::TimedOut(secs))?,
+        None => request.await,
+    };
+
+    request
+        .map_err(Error::request)?
         .response()
-        .expect("failed to receive screenshot response");
-
-    let uri = response.uri();
-    let path = match uri.scheme() {
-        "file" => {
-            if let Some(picture_dir) = picture_dir {
-                let date = chrono::Local::now();
-                let filename = format!("Screenshot_{}.png", date.format("%Y-%m-%d_%H-%M-%S"));
-                let path = picture_dir.join(filename);
-                let tmp_path = uri.path();
-                if fs::metadata(&picture_dir)
-                    .expect("Failed to get medatata on filesystem for screenshot destination")
-                    .dev()
-                    != fs::metadata(tmp_path)
-                        .expect("Failed to get metadata on filesystem for temporary path")
-                        .dev()
-                {
-                    // copy file instead
-                    fs::copy(tmp_path, &path).expect("failed to move screenshot");
-                    fs::remove_file(tmp_path).expect("failed to remove temporary screenshot");
-                } else {
-                    fs::rename(tmp_path, &path).expect("failed to move screenshot");
-                }
+        .map_err(Error::response)
+}
 
-                path.to_string_lossy().to_string()
-            } else {
-                uri.path().to_string()
-            }
+/// Move the portal's screenshot to `path`, re-encoding it if needed
+///
+/// `path` may be `tmp_path` itself when the screenshot only needs to be
+/// re-encoded in place. Returns where the screenshot was saved, which
+/// differs from `path` if encoding failed and it was saved uncompressed
+/// instead.
+fn move_screenshot(tmp_path: &Path, path: &Path, args: &Args) -> Result<PathBuf, Error> {
+    if needs_reencode(args) {
+        let image = prepare(args, image::open(tmp_path)?);
+        let path = save::save(&image, path, args.format, quality(args))?;
+        if path != tmp_path {
+            remove_temporary(tmp_path)?;
+        }
+        return Ok(path);
+    }
+
+    // metadata follows symlinks, so a symlinked picture directory reports the
+    // filesystem the file lands on
+    let picture_dir = path.parent().unwrap_or(Path::new("."));
+    let dest_dev = fs::metadata(picture_dir)
+        .map_err(|err| Error::Io("failed to get metadata for screenshot destination", err))?
+        .dev();
+    let tmp_dev = fs::metadata(tmp_path)
+        .map_err(|err| Error::Io("failed to get metadata for temporary path", err))?
+        .dev();
+    if dest_dev != tmp_dev {
+        // copy file instead
+        fs::copy(tmp_path, path).map_err(|err| Error::Io("failed to move screenshot", err))?;
+        remove_temporary(tmp_path)?;
+    } else {
+        fs::rename(tmp_path, path).map_err(|err| Error::Io("failed to move screenshot", err))?;
+    }
+
+    Ok(path.to_owned())
+}
+
+/// Write encoded image data to `out`, adding metadata to PNGs
+fn write_image(args: &Args, data: Vec<u8>, out: &mut impl Write) -> io::Result<()> {
+    let data = if args.metadata && args.format == save::Format::Png {
+        metadata::insert_png_text_chunks(&data, &metadata::capture_metadata()).unwrap_or(data)
+    } else {
+        data
+    };
+
+    out.write_all(&data)?;
+    out.flush()
+}
+
+/// Write encoded image data to stdout, adding metadata to PNGs
+fn write_stdout(args: &Args, data: Vec<u8>) -> Result<(), Error> {
+    write_image(args, data, &mut io::stdout().lock())
+        .map_err(|err| Error::Io("failed to write screenshot to stdout", err))
+}
+
+/// Write the portal's screenshot to stdout, re-encoding it if needed
+fn screenshot_to_stdout(tmp_path: &Path, args: &Args) -> Result<(), Error> {
+    let data = if needs_reencode(args) {
+        let image = prepare(args, image::open(tmp_path)?);
+        save::encode(&image, args.format, quality(args))?
+    } else {
+        fs::read(tmp_path).map_err(|err| Error::Io("failed to read screenshot", err))?
+    };
+    write_stdout(args, data)
+}
+
+/// Remove a temporary screenshot written by the portal
+fn remove_temporary(tmp_path: &Path) -> Result<(), Error> {
+    fs::remove_file(tmp_path).map_err(|err| Error::Io("failed to remove temporary screenshot", err))
+}
+
+/// Take `count` screenshots and save them combined into a single image
+async fn compose_screenshots(
+    args: &Args,
+    count: u32,
+    picture_dir: Option<&Path>,
+) -> Result<Saved, Error> {
+    let mut images = Vec::new();
+    for _ in 0..count {
+        let response = request_screenshot(args).await?;
+        let tmp_path = portal::screenshot_file(response.uri())?;
+        images.push(image::open(&tmp_path)?);
+        // interactive captures were saved where the user chose, keep them
+        if picture_dir.is_some() {
+            remove_temporary(&tmp_path)?;
         }
-        "clipboard" => String::new(),
-        scheme => panic!("unsupported scheme '{}'", scheme),
+    }
+
+    let composed = prepare(
+        args,
+        DynamicImage::from(compose::compose(&images, args.compose_layout)),
+    );
+    if args.stdout {
+        write_stdout(args, save::encode(&composed, args.format, quality(args))?)?;
+        return Ok(Saved::Stdout);
+    }
+
+    let picture_dir = match picture_dir {
+        Some(picture_dir) => picture_dir.to_owned(),
+        None => save_dir(args)?,
     };
+    let path = screenshot_path(args, &picture_dir)?;
+    Ok(Saved::File(save::save(
+        &composed,
+        &path,
+        args.format,
+        quality(args),
+    )?))
+}
 
-    println!("{path}");
+/// Take `count` screenshots and return the path of the sharpest, removing
+/// the others
+///
+/// Interactive captures are saved where the user chose, so they are kept.
+async fn sharpest_screenshot(
+    args: &Args,
+    count: u32,
+    remove_others: bool,
+) -> Result<PathBuf, Error> {
+    /// Take a screenshot and score its sharpness
+    async fn scored(args: &Args) -> Result<(f64, PathBuf), Error> {
+        let response = request_screenshot(args).await?;
+        let path = portal::screenshot_file(response.uri())?;
+        Ok((sharpness::sharpness(&image::open(&path)?), path))
+    }
+
+    let mut sharpest = scored(args).await?;
+    for _ in 1..count {
+        let (score, path) = scored(args).await?;
+        let discarded = if score > sharpest.0 {
+            std::mem::replace(&mut sharpest, (score, path)).1
+        } else {
+            path
+        };
+        if remove_others {
+            remove_temporary(&discarded)?;
+        }
+    }
+
+    Ok(sharpest.1)
+}
+
+/// Reject options that take several captures in one run in interactive mode
+///
+/// Every capture would open a dialog and leave its file behind.
+fn check_non_interactive(args: &Args) -> Result<(), Error> {
+    if !args.interactive {
+        return Ok(());
+    }
+    let arg = if args.sharpest_of.is_some() {
+        "--sharpest-of <COUNT>"
+    } else if args.interval.is_some() {
+        "--interval <SECONDS>"
+    } else {
+        return Ok(());
+    };
+    let message = format!("the argument '{arg}' requires '--interactive=false'");
+    Err(Error::Arguments(
+        Args::command().error(ErrorKind::ArgumentConflict, message),
+    ))
+}
+
+/// The format of the contact sheet at `sheet`, named by its extension
+///
+/// The sheet is read back on the next --append-to, so it can't be written
+/// under an extension that doesn't match its format.
+fn sheet_format(sheet: &Path) -> Result<save::Format, Error> {
+    if let Some(format) = save::Format::from_path(sheet) {
+        return Ok(format);
+    }
+    let message = format!(
+        "invalid value '{}' for '--append-to <FILE>': the extension must be one of {}",
+        sheet.display(),
+        save::Format::value_variants()
+            .iter()
+            .map(|format| format.extension())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Err(Error::Arguments(
+        Args::command().error(ErrorKind::ValueValidation, message),
+    ))
+}
+
+/// Add `image` as a new tile to the contact sheet at `sheet`, creating it if needed
+///
+/// The format of the sheet follows from its extension, see [`sheet_format`].
+fn append_to_sheet(args: &Args, sheet: &Path, image: DynamicImage) -> Result<(), Error> {
+    let image = if sheet.exists() {
+        let existing = image::open(sheet)?;
+        DynamicImage::from(compose::compose(&[existing, image], args.compose_layout))
+    } else {
+        image
+    };
+
+    // write next to the sheet first so a failed save doesn't lose earlier tiles
+    let format = sheet_format(sheet)?;
+    let mut tmp_name = sheet.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = sheet.with_file_name(tmp_name);
+    let written = save::save(&image, &tmp_path, format, quality(args))?;
+    // an uncompressed fallback must not replace the sheet under its extension
+    let sheet = if written == tmp_path {
+        sheet.to_owned()
+    } else {
+        sheet.with_extension("ppm")
+    };
+    fs::rename(&written, sheet).map_err(|err| Error::Io("failed to update contact sheet", err))
+}
+
+/// Record the capture metadata in the screenshot at `path` if it is a PNG
+///
+/// The screenshot is already saved, so failures are only warned about.
+fn add_metadata(path: &Path) {
+    if save::Format::from_path(path) != Some(save::Format::Png) {
+        return;
+    }
+    if let Err(err) = metadata::write_png_text_chunks(path, &metadata::capture_metadata()) {
+        eprintln!("{err}");
+    }
+}
+
+/// Report a saved screenshot and run the actions requested for it
+///
+/// The screenshot is already saved, so failures of the on-save and
+/// --open-with commands are only warned about. A notification that can't be
+/// sent is returned as an error once everything else has run.
+async fn post_process(args: &Args, saved: &Saved) -> Result<(), Error> {
+    match saved {
+        Saved::File(path) => println!("{}", path.display()),
+        // with --stdout nothing but image data may go to stdout
+        Saved::Clipboard if args.stdout => {}
+        Saved::Clipboard => println!(),
+        Saved::Stdout => {}
+    }
+
+    if let (Some(command), Saved::File(path)) = (&args.on_save, saved) {
+        hook::run(command, &path.to_string_lossy()).await;
+    }
+
+    if let (Some(command), Saved::File(path)) = (&args.open_with, saved) {
+        hook::open_with(command, &path.to_string_lossy());
+    }
 
     if args.notify {
-        let connection = Connection::session()
-            .await
-            .expect("failed to connect to session bus");
+        let (message, path) = match saved {
+            Saved::File(path) => ("Screenshot saved to:", Some(path.as_path())),
+            Saved::Clipboard => ("Screenshot saved to clipboard", None),
+            Saved::Stdout => ("Screenshot written to stdout", None),
+        };
+        let body = path.map_or_else(String::new, |path| path.to_string_lossy().into_owned());
+        match notification::notify(message, &body, path).await {
+            // give the user a chance to click the notification before we exit;
+            // watch and --interval keep running and handle clicks in the background
+            Ok(Some(listener)) if args.command.is_none() && args.interval.is_none() => {
+                _ = listener.await;
+            }
+            Ok(_) => {}
+            Err(err) => return Err(Error::Notification(err)),
+        }
+    }
+
+    Ok(())
+}
 
-        let message = if path.is_empty() {
-            "Screenshot saved to clipboard"
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err.to_user_facing());
+            err.exit_code()
+        }
+    }
+}
+
+async fn run() -> Result<(), Error> {
+    let mut args = match Args::try_parse() {
+        Ok(args) => args,
+        // --help and --version are printed to stdout and succeed
+        Err(err) if !err.use_stderr() => err.exit(),
+        Err(err) => return Err(Error::Arguments(err)),
+    };
+
+    // completions need neither a display nor the portal
+    if let Some(Command::Completions { shell }) = args.command {
+        let mut command = Args::command();
+        let name = command.get_name().to_owned();
+        clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+        return Ok(());
+    }
+
+    if let Err(message) = naming::validate(&args.name_template, args.allow_subdirs) {
+        let message = format!(
+            "invalid value '{}' for '--name-template <TEMPLATE>': {message}",
+            args.name_template
+        );
+        return Err(Error::Arguments(
+            Args::command().error(ErrorKind::ValueValidation, message),
+        ));
+    }
+
+    if let Some(sheet) = &args.append_to {
+        sheet_format(sheet)?;
+    }
+
+    check_non_interactive(&args)?;
+
+    add_interval_counter(&mut args);
+
+    if !has_display() {
+        return Err(Error::NoDisplay);
+    }
+
+    if args.quality.is_some() && args.format.is_lossless() {
+        eprintln!(
+            "--quality has no effect on lossless {} output",
+            args.format.extension()
+        );
+    }
+
+    match args.command {
+        Some(Command::CopyLast) => return copy_last(&args),
+        Some(Command::Watch { ref dir, copy }) => return watch::watch(&args, dir, copy).await,
+        Some(Command::Completions { .. }) | None => {}
+    }
+
+    let picture_dir = if args.interactive {
+        None
+    } else {
+        Some(save_dir(&args)?)
+    };
+
+    // a missing portal would otherwise only show up after --delay or --wait-idle
+    if let Err(err) = portal::check_available().await {
+        if portal::is_unavailable(&err) {
+            return Err(Error::PortalUnavailable(err));
+        }
+    }
+
+    let supports_non_interactive = portal::supports_non_interactive().await;
+    if !args.interactive && !supports_non_interactive {
+        eprintln!("screenshot portal ignores --interactive=false, a dialog will be shown");
+        args.interactive = true;
+        check_non_interactive(&args)?;
+    }
+
+    if let Some(duration) = args.delay {
+        let mut stderr = io::stderr();
+        let countdown = (!args.no_countdown).then_some(&mut stderr as &mut dyn Write);
+        delay(duration, countdown).await;
+    }
+
+    if let Some(stable_for) = args.wait_idle {
+        // every probe would open a dialog on portals that ignore `interactive`
+        if supports_non_interactive {
+            idle::wait_for_idle(Duration::from_millis(stable_for)).await?;
         } else {
-            "Screenshot saved to:"
+            eprintln!(
+                "screenshot portal can't take non-interactive captures, ignoring --wait-idle"
+            );
+        }
+    }
+
+    let Some(interval) = args.interval else {
+        let saved = capture(&args, picture_dir.as_deref()).await?;
+        let processed = post_process(&args, &saved).await;
+
+        #[cfg(feature = "serve")]
+        if let Some(port) = args.serve {
+            match saved {
+                Saved::File(path) => serve::serve(&path, port)?,
+                Saved::Clipboard => {
+                    eprintln!("nothing to serve: screenshot was saved to the clipboard")
+                }
+                Saved::Stdout => eprintln!("nothing to serve: screenshot was written to stdout"),
+            }
+        }
+
+        return processed;
+    };
+
+    capture_repeatedly(&args, picture_dir.as_deref(), interval).await
+}
+
+/// Take a screenshot and save it where requested
+///
+/// `picture_dir` is `None` for interactive captures, which are kept where
+/// the portal saved them.
+async fn capture(args: &Args, picture_dir: Option<&Path>) -> Result<Saved, Error> {
+    let saved = if let Some(count) = args.compose {
+        compose_screenshots(args, count, picture_dir).await?
+    } else {
+        // the portal's file, or `None` if it put the screenshot on the clipboard
+        let tmp_path = match args.sharpest_of {
+            Some(count) => Some(sharpest_screenshot(args, count, picture_dir.is_some()).await?),
+            None => {
+                let response = request_screenshot(args).await?;
+                match response.uri().scheme() {
+                    "clipboard" => None,
+                    _ => Some(portal::screenshot_file(response.uri())?),
+                }
+            }
         };
-        let proxy = NotificationsProxy::new(&connection)
-            .await
-            .expect("failed to create proxy");
-        _ = proxy
-            .notify(
-                "COSMIC Screenshot",
-                0,
-                "com.system76.CosmicScreenshot",
-                message,
-                &path,
-                &[],
-                HashMap::from([("transient", &Value::Bool(true))]),
-                5000,
-            )
-            .await
-            .expect("failed to send notification");
+
+        match tmp_path {
+            None => Saved::Clipboard,
+            Some(tmp_path) if args.stdout => {
+                screenshot_to_stdout(&tmp_path, args)?;
+                // interactive captures were saved where the user chose, keep them
+                if picture_dir.is_some() {
+                    remove_temporary(&tmp_path)?;
+                }
+
+                Saved::Stdout
+            }
+            Some(tmp_path) => {
+                let path = match picture_dir {
+                    Some(picture_dir) => screenshot_path(args, picture_dir)?,
+                    // keep interactive captures where the portal saved them
+                    None => tmp_path.with_extension(args.format.extension()),
+                };
+                if path == tmp_path && !needs_reencode(args) {
+                    Saved::File(path)
+                } else {
+                    Saved::File(move_screenshot(&tmp_path, &path, args)?)
+                }
+            }
+        }
+    };
+
+    if let Saved::File(path) = &saved {
+        if args.metadata {
+            add_metadata(path);
+        }
+    }
+
+    if let Some(sheet) = &args.append_to {
+        match &saved {
+            Saved::File(path) => append_to_sheet(args, sheet, image::open(path)?)?,
+            _ => eprintln!("nothing to append: screenshot was not saved to a file"),
+        }
+    }
+
+    Ok(saved)
+}
+
+/// Append a counter to --name-template for --interval if it has none
+///
+/// Screenshots taken within the same second would overwrite each other.
+fn add_interval_counter(args: &mut Args) {
+    if args.interval.is_some() && !args.name_template.contains("%n") {
+        args.name_template.push_str("_%n");
+    }
+}
+
+/// Returns true once --interval has taken the `taken` screenshots --count asks for
+fn interval_finished(args: &Args, taken: u32) -> bool {
+    args.count.is_some_and(|count| taken >= count)
+}
+
+/// Take a screenshot every `interval` until --count screenshots are taken or
+/// Ctrl+C is pressed
+///
+/// Ctrl+C during a capture lets it finish and be saved before stopping.
+async fn capture_repeatedly(
+    args: &Args,
+    picture_dir: Option<&Path>,
+    interval: Duration,
+) -> Result<(), Error> {
+    let mut interrupt = signal(SignalKind::interrupt())
+        .map_err(|err| Error::Io("failed to listen for Ctrl+C", err))?;
+
+    let mut taken = 0;
+    loop {
+        let saved = capture(args, picture_dir).await?;
+        // a missed notification shouldn't end the series
+        if let Err(err) = post_process(args, &saved).await {
+            eprintln!("{err}");
+        }
+        taken += 1;
+        if interval_finished(args, taken) {
+            return Ok(());
+        }
+
+        tokio::select! {
+            _ = interrupt.recv() => {
+                eprintln!("stopped after {taken} screenshots");
+                return Ok(());
+            }
+            () = tokio::time::sleep(interval) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn args(argv: &[&str]) -> Args {
+        Args::try_parse_from(std::iter::once("cosmic-screenshot").chain(argv.iter().copied()))
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_seconds_accepts_fractions_and_zero() {
+        assert_eq!(parse_seconds("2.5"), Ok(Duration::from_millis(2500)));
+        assert_eq!(parse_seconds("3"), Ok(Duration::from_secs(3)));
+        assert_eq!(parse_seconds("0"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn parse_seconds_rejects_invalid_input() {
+        for secs in ["", "soon", "-1", "NaN", "inf"] {
+            assert!(parse_seconds(secs).is_err(), "{secs:?} was accepted");
+        }
+        assert!(Args::try_parse_from(["cosmic-screenshot", "--delay", "-2"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn zero_delay_skips_the_countdown() {
+        let args = args(&["--delay", "0"]);
+        let mut out = Vec::new();
+        delay(args.delay.unwrap(), Some(&mut out)).await;
+        assert!(out.is_empty());
+    }
+
+    #[tokio::test]
+    async fn delay_counts_down_whole_seconds() {
+        let mut out = Vec::new();
+        delay(Duration::from_millis(1100), Some(&mut out)).await;
+        assert_eq!(String::from_utf8(out).unwrap(), "Taking screenshot in 1…\n");
+    }
+
+    #[test]
+    fn sharpest_of_requires_non_interactive() {
+        let err = check_non_interactive(&args(&["--sharpest-of", "3"])).unwrap_err();
+        assert!(matches!(err, Error::Arguments(_)), "{err}");
+        assert!(
+            check_non_interactive(&args(&["--sharpest-of", "3", "--interactive=false"])).is_ok()
+        );
+        assert!(check_non_interactive(&args(&[])).is_ok());
+    }
+
+    #[test]
+    fn interval_requires_non_interactive() {
+        let err = check_non_interactive(&args(&["--interval", "1"])).unwrap_err();
+        assert!(matches!(err, Error::Arguments(_)), "{err}");
+        assert!(check_non_interactive(&args(&["--interval", "1", "--interactive=false"])).is_ok());
+    }
+
+    #[test]
+    fn interval_conflicts_with_stdout() {
+        let argv = ["cosmic-screenshot", "--interval", "1", "--stdout"];
+        let err = Args::try_parse_from(argv).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+        assert!(Args::try_parse_from(["cosmic-screenshot", "--count", "2"]).is_err());
+    }
+
+    #[test]
+    fn interval_stops_after_count() {
+        let counted = args(&["--interval", "1", "--count", "3"]);
+        let finished: Vec<bool> = (1..=4)
+            .map(|taken| interval_finished(&counted, taken))
+            .collect();
+        assert_eq!(finished, [false, false, true, true]);
+
+        // without --count only Ctrl+C stops
+        let endless = args(&["--interval", "1"]);
+        assert!(!(1..100).any(|taken| interval_finished(&endless, taken)));
+    }
+
+    #[test]
+    fn interval_screenshots_are_numbered() {
+        let dir = test_dir("interval");
+        let mut numbered = args(&["--interval", "1", "--name-template", "shot"]);
+        add_interval_counter(&mut numbered);
+        assert_eq!(numbered.name_template, "shot_%n");
+        for expected in ["shot_1.png", "shot_2.png", "shot_3.png"] {
+            let path = screenshot_path(&numbered, &dir).unwrap();
+            assert_eq!(path, dir.join(expected));
+            fs::write(&path, b"").unwrap();
+        }
+
+        // a template with its own counter is left alone
+        let mut custom = args(&["--interval", "1", "--name-template", "%n-shot"]);
+        add_interval_counter(&mut custom);
+        assert_eq!(custom.name_template, "%n-shot");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn completions_for_every_shell() {
+        use clap_complete::Shell;
+
+        for &shell in Shell::value_variants() {
+            let mut command = Args::command();
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut command, "cosmic-screenshot", &mut script);
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("cosmic-screenshot"), "{shell}");
+            assert!(script.contains("name-template"), "{shell}");
+        }
+    }
+
+    #[test]
+    fn latest_screenshot_skips_other_images() {
+        let dir = test_dir("latest");
+        let set_age = |name: &str, secs_ago| {
+            let file = fs::File::create(dir.join(name)).unwrap();
+            let time = std::time::SystemTime::now() - Duration::from_secs(secs_ago);
+            file.set_modified(time).unwrap();
+        };
+        set_age("Screenshot_2024-03-09_14-05-07.png", 300);
+        set_age("Screenshot_2024-03-09_14-06-00.jpg", 200);
+        set_age("IMG_1234.jpg", 100);
+        set_age("wallpaper.png", 0);
+
+        let (path, format) = latest_screenshot(&dir, naming::DEFAULT_TEMPLATE)
+            .unwrap()
+            .unwrap();
+        assert_eq!(path, dir.join("Screenshot_2024-03-09_14-06-00.jpg"));
+        assert_eq!(format, ImageFormat::Jpeg);
+        assert_eq!(latest_screenshot(&dir, "%o_%n").unwrap(), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sheet_needs_a_known_extension() {
+        assert_eq!(
+            sheet_format(Path::new("sheet.png")).ok(),
+            Some(save::Format::Png)
+        );
+        assert_eq!(
+            sheet_format(Path::new("dir/sheet.JPG")).ok(),
+            Some(save::Format::Jpeg)
+        );
+        for sheet in ["sheet", "sheet.txt", ".png"] {
+            let err = sheet_format(Path::new(sheet)).unwrap_err();
+            assert!(matches!(err, Error::Arguments(_)), "{sheet:?}: {err}");
+            assert_eq!(err.exit_code(), ExitCode::from(4));
+        }
+    }
+
+    /// A fresh directory under the system temporary directory
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("cosmic-screenshot-{}-{name}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn save_dir_resolution_order() {
+        let dir = test_dir("save-dir");
+        let [save, pictures, screenshots, user, home] =
+            ["save", "pictures", "screenshots", "user", "home"].map(|name| dir.join(name));
+        let var = |name: &str| match name {
+            "XDG_PICTURES_DIR" => Some(pictures.clone().into_os_string()),
+            "XDG_SCREENSHOTS_DIR" => Some(screenshots.clone().into_os_string()),
+            _ => None,
+        };
+        let resolve = |mkdir| {
+            let candidates = save_dir_candidates(Some(&save), var, Some(user.clone()), Some(&home));
+            first_save_dir(candidates, mkdir).unwrap()
+        };
+
+        // nothing exists yet, and only --mkdir creates --save-dir
+        assert_eq!(resolve(false), None);
+        assert_eq!(resolve(true), Some(save.clone()));
+
+        // each directory wins over the ones after it
+        for expected in [&save, &pictures, &screenshots, &user] {
+            fs::create_dir_all(expected).unwrap();
+            assert_eq!(resolve(false).as_ref(), Some(expected));
+            fs::remove_dir(expected).unwrap();
+        }
+        fs::create_dir_all(home.join("Pictures")).unwrap();
+        assert_eq!(resolve(false), Some(home.join("Pictures")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn empty_variables_are_ignored() {
+        let candidates = save_dir_candidates(None, |_| Some(OsString::new()), None, None);
+        assert!(candidates.iter().all(|(dir, _)| dir.is_none()));
+    }
+
+    #[test]
+    fn move_into_symlinked_directory() {
+        let dir = test_dir("symlink");
+        let target = dir.join("target");
+        fs::create_dir(&target).unwrap();
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let tmp_path = dir.join("tmp.png");
+        fs::write(&tmp_path, b"screenshot").unwrap();
+
+        let args = args(&[]);
+        let saved = move_screenshot(&tmp_path, &link.join("shot.png"), &args).unwrap();
+        assert_eq!(saved, link.join("shot.png"));
+        assert_eq!(fs::read(target.join("shot.png")).unwrap(), b"screenshot");
+        assert!(!tmp_path.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stdout_gets_only_png_data() {
+        let args = args(&["--stdout"]);
+        let image = DynamicImage::from(RgbaImage::from_pixel(4, 3, Rgba([1, 2, 3, 255])));
+        let data = save::encode(&image, args.format, quality(&args)).unwrap();
+
+        let mut out = Vec::new();
+        write_image(&args, data, &mut out).unwrap();
+        assert!(out.starts_with(b"\x89PNG\r\n\x1a\n"));
+        let decoded = image::load_from_memory_with_format(&out, ImageFormat::Png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (4, 3));
     }
 }
@@ -0,0 +1,148 @@
+use crate::error::Error;
+use std::{env, fs, io, iter, path::Path};
+
+/// The signature every PNG file starts with
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// The length of the IHDR chunk, which always comes first, including its
+/// length, type and CRC fields
+const IHDR_CHUNK_LEN: usize = 4 + 4 + 13 + 4;
+
+/// The metadata recorded for a screenshot taken now
+///
+/// The portal doesn't say which output was captured, so unlike the time,
+/// tool version and desktop, the output name is not recorded.
+pub fn capture_metadata() -> Vec<(&'static str, String)> {
+    let mut metadata = vec![
+        (
+            "Software",
+            format!("cosmic-screenshot {}", env!("CARGO_PKG_VERSION")),
+        ),
+        ("Creation Time", chrono::Local::now().to_rfc2822()),
+    ];
+    if let Some(desktop) = env::var("XDG_CURRENT_DESKTOP")
+        .ok()
+        .filter(|desktop| !desktop.is_empty())
+    {
+        metadata.push(("Compositor", desktop));
+    }
+    metadata
+}
+
+/// Encode a tEXt chunk, replacing characters outside Latin-1 with `?`
+fn text_chunk(keyword: &str, text: &str) -> Vec<u8> {
+    let data: Vec<u8> = keyword
+        .chars()
+        .chain(iter::once('\0'))
+        .chain(text.chars())
+        .map(|c| u8::try_from(c).unwrap_or(b'?'))
+        .collect();
+
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(b"tEXt");
+    chunk.extend_from_slice(&data);
+    // the CRC covers the chunk type and data, not the length
+    let crc = crc32fast::hash(&chunk[4..]);
+    chunk.extend_from_slice(&crc.to_be_bytes());
+    chunk
+}
+
+/// Insert tEXt chunks for `entries` right after the IHDR chunk of a PNG
+///
+/// Returns `None` if `data` is not a PNG.
+pub fn insert_png_text_chunks(data: &[u8], entries: &[(&str, String)]) -> Option<Vec<u8>> {
+    let header_len = PNG_SIGNATURE.len() + IHDR_CHUNK_LEN;
+    if data.len() < header_len || !data.starts_with(PNG_SIGNATURE) || &data[12..16] != b"IHDR" {
+        return None;
+    }
+    let (header, rest) = data.split_at(header_len);
+
+    let mut stamped = header.to_vec();
+    for (keyword, text) in entries {
+        stamped.extend(text_chunk(keyword, text));
+    }
+    stamped.extend_from_slice(rest);
+    Some(stamped)
+}
+
+/// Add tEXt chunks for `entries` to the PNG file at `path`
+pub fn write_png_text_chunks(path: &Path, entries: &[(&str, String)]) -> Result<(), Error> {
+    let data = fs::read(path).map_err(|err| Error::Io("failed to read screenshot", err))?;
+    let data = insert_png_text_chunks(&data, entries).ok_or_else(|| {
+        Error::Io(
+            "failed to add screenshot metadata",
+            io::Error::new(io::ErrorKind::InvalidData, "not a PNG file"),
+        )
+    })?;
+    fs::write(path, data).map_err(|err| Error::Io("failed to add screenshot metadata", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::save;
+    use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+
+    /// The type and data of every chunk of a PNG, checking each CRC
+    fn chunks(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        assert!(data.starts_with(PNG_SIGNATURE));
+        let mut rest = &data[PNG_SIGNATURE.len()..];
+        let mut chunks = Vec::new();
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (body, tail) = rest[4..].split_at(4 + len);
+            let crc = u32::from_be_bytes(tail[..4].try_into().unwrap());
+            assert_eq!(crc32fast::hash(body), crc, "bad CRC in {:?}", &body[..4]);
+            chunks.push((body[..4].try_into().unwrap(), &body[4..]));
+            rest = &tail[4..];
+        }
+        chunks
+    }
+
+    fn png() -> Vec<u8> {
+        let image = DynamicImage::from(RgbaImage::from_pixel(5, 4, Rgba([9, 8, 7, 255])));
+        save::encode(&image, save::Format::Png, save::DEFAULT_QUALITY).unwrap()
+    }
+
+    #[test]
+    fn text_chunks_round_trip() {
+        let entries = [
+            ("Software", String::from("cosmic-screenshot 1.0")),
+            ("Compositor", String::from("COSMIC")),
+        ];
+        let stamped = insert_png_text_chunks(&png(), &entries).unwrap();
+
+        let chunks = chunks(&stamped);
+        assert_eq!(&chunks[0].0, b"IHDR");
+        let texts: Vec<&[u8]> = chunks
+            .iter()
+            .filter(|(kind, _)| kind == b"tEXt")
+            .map(|(_, data)| *data)
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                &b"Software\0cosmic-screenshot 1.0"[..],
+                &b"Compositor\0COSMIC"[..]
+            ]
+        );
+        assert_eq!(&chunks[1].0, b"tEXt");
+
+        let decoded = image::load_from_memory_with_format(&stamped, ImageFormat::Png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (5, 4));
+    }
+
+    #[test]
+    fn text_outside_latin1_is_replaced() {
+        let stamped =
+            insert_png_text_chunks(&png(), &[("Title", String::from("café ☕"))]).unwrap();
+        let chunks = chunks(&stamped);
+        assert_eq!(chunks[1], (*b"tEXt", &b"Title\0caf\xe9 ?"[..]));
+    }
+
+    #[test]
+    fn only_pngs_are_stamped() {
+        assert_eq!(insert_png_text_chunks(b"GIF89a", &[]), None);
+        assert_eq!(insert_png_text_chunks(PNG_SIGNATURE, &[]), None);
+    }
+}
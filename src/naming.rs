@@ -0,0 +1,253 @@
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Local,
+};
+use std::path::{Component, Path, PathBuf};
+
+/// The file name screenshots are saved under, without the extension
+pub const DEFAULT_TEMPLATE: &str = "Screenshot_%Y-%m-%d_%H-%M-%S";
+
+/// What `%o` expands to, as the portal doesn't report which output was captured
+const OUTPUT_NAME: &str = "screen";
+
+/// Expand the `%o` and `%n` tokens, leaving strftime tokens for chrono
+fn substitute(template: &str, counter: u32) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('o') => expanded.push_str(OUTPUT_NAME),
+            Some('n') => expanded.push_str(&counter.to_string()),
+            Some(c) => {
+                expanded.push('%');
+                expanded.push(c);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+/// A piece of a template when matching file names against it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Piece {
+    /// A character the name must contain at this point
+    Literal(char),
+    /// A token, matching any run of characters
+    Any,
+}
+
+/// Split `template` into the pieces a name expanded from it must match
+fn pieces(template: &str) -> Vec<Piece> {
+    let mut pieces = Vec::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            pieces.push(Piece::Literal(c));
+            continue;
+        }
+        match chars.next() {
+            Some('%') => pieces.push(Piece::Literal('%')),
+            Some('o') => pieces.extend(OUTPUT_NAME.chars().map(Piece::Literal)),
+            // strftime tokens may have flags and widths, as in %-d or %.3f
+            Some(c) => {
+                if !c.is_ascii_alphabetic() {
+                    for c in chars.by_ref() {
+                        if c.is_ascii_alphabetic() {
+                            break;
+                        }
+                    }
+                }
+                pieces.push(Piece::Any);
+            }
+            None => pieces.push(Piece::Literal('%')),
+        }
+    }
+    pieces
+}
+
+/// Returns true if `name`, a file name without its extension, may have been
+/// expanded from `template`
+///
+/// Tokens other than `%o` match any text, so some names the template can't
+/// produce are accepted, but none it can produce are rejected.
+pub fn matches(template: &str, name: &str) -> bool {
+    let pieces = pieces(template);
+    let name: Vec<char> = name.chars().collect();
+    // matched[j] is true if the pieces so far can expand to name[..j]
+    let mut matched = vec![false; name.len() + 1];
+    matched[0] = true;
+    for piece in pieces {
+        matched = match piece {
+            Piece::Literal(c) => (0..=name.len())
+                .map(|j| j > 0 && matched[j - 1] && name[j - 1] == c)
+                .collect(),
+            Piece::Any => matched
+                .iter()
+                .scan(false, |any, &m| {
+                    *any |= m;
+                    Some(*any)
+                })
+                .collect(),
+        };
+    }
+    matched[name.len()]
+}
+
+/// Check that `template` expands to a file name, or a relative path with
+/// `allow_subdirs`, describing the problem if it doesn't
+///
+/// The expanded name is checked as well as the template, as strftime tokens
+/// such as `%D` expand to text containing '/'.
+pub fn validate(template: &str, allow_subdirs: bool) -> Result<(), String> {
+    if template.is_empty() {
+        return Err(String::from("the template is empty"));
+    }
+    if StrftimeItems::new(&substitute(template, 1)).any(|item| item == Item::Error) {
+        return Err(String::from("the template contains an invalid % token"));
+    }
+
+    let expanded = Local::now().format(&substitute(template, 1)).to_string();
+    for name in [template, &expanded] {
+        if name.contains('/') && !allow_subdirs {
+            return Err(String::from(
+                "templates expanding to a name containing '/' require --allow-subdirs",
+            ));
+        }
+        if !Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(String::from(
+                "the template must be a relative path without '.' or '..'",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The path in `dir` for a screenshot taken at `time`, named after `template`
+///
+/// `template` takes strftime tokens, `%o` for the output name and `%n` for a
+/// counter. The counter starts at 1 and is increased until the path doesn't
+/// exist yet. The template must have passed [`validate`].
+pub fn render(template: &str, dir: &Path, time: &DateTime<Local>, extension: &str) -> PathBuf {
+    let path = |counter| {
+        let name = time.format(&substitute(template, counter)).to_string();
+        dir.join(format!("{name}.{extension}"))
+    };
+
+    // only a template with %n expands differently for each counter
+    if substitute(template, 0) == substitute(template, 1) {
+        return path(1);
+    }
+    (1..)
+        .map(path)
+        .find(|path| !path.exists())
+        .expect("ran out of screenshot counter values")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::{env, fs};
+
+    fn time() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, 9, 14, 5, 7).unwrap()
+    }
+
+    #[test]
+    fn tokens_are_expanded() {
+        let dir = Path::new("/nonexistent");
+        let cases = [
+            (DEFAULT_TEMPLATE, "Screenshot_2024-03-09_14-05-07"),
+            ("%o", "screen"),
+            ("shot_%n", "shot_1"),
+            ("%o_%y%m%d", "screen_240309"),
+            ("100%%", "100%"),
+        ];
+        for (template, name) in cases {
+            assert_eq!(
+                render(template, dir, &time(), "png"),
+                dir.join(format!("{name}.png")),
+                "{template}"
+            );
+        }
+    }
+
+    #[test]
+    fn counter_skips_existing_files() {
+        let dir = env::temp_dir().join(format!("cosmic-screenshot-{}-naming", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        for expected in ["shot_1.png", "shot_2.png", "shot_3.png"] {
+            let path = render("shot_%n", &dir, &time(), "png");
+            assert_eq!(path, dir.join(expected));
+            fs::write(&path, b"").unwrap();
+        }
+        // without %n an existing file is overwritten rather than renamed
+        fs::write(dir.join("shot.png"), b"").unwrap();
+        assert_eq!(render("shot", &dir, &time(), "png"), dir.join("shot.png"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn names_matching_templates() {
+        let cases = [
+            (DEFAULT_TEMPLATE, "Screenshot_2024-03-09_14-05-07", true),
+            (DEFAULT_TEMPLATE, "Screenshot_2024-03-09", false),
+            (DEFAULT_TEMPLATE, "IMG_1234", false),
+            (DEFAULT_TEMPLATE, "wallpaper", false),
+            ("%o_%n", "screen_12", true),
+            ("%o_%n", "photo_12", false),
+            ("shot_%-d.%m", "shot_9.03", true),
+            ("shot_%-d.%m", "shot_9-03", false),
+            ("100%%_%n", "100%_1", true),
+            ("shot", "shot", true),
+            ("shot", "shot_1", false),
+        ];
+        for (template, name, expected) in cases {
+            assert_eq!(matches(template, name), expected, "{template} {name}");
+        }
+    }
+
+    #[test]
+    fn valid_templates() {
+        for template in [DEFAULT_TEMPLATE, "%o_%n", "shot", "100%%"] {
+            assert_eq!(validate(template, false), Ok(()), "{template}");
+        }
+        for template in ["%Y/%m/shot", "%D", "%x_%n"] {
+            assert_eq!(validate(template, true), Ok(()), "{template}");
+        }
+    }
+
+    #[test]
+    fn invalid_templates() {
+        let cases = [
+            ("", true),
+            ("a/b", false),
+            ("%D", false),
+            ("shot_%x", false),
+            ("../shot", true),
+            ("a/../b", true),
+            ("./shot", true),
+            ("/tmp/shot", true),
+            ("shot_%Q", false),
+            ("shot_%", false),
+        ];
+        for (template, allow_subdirs) in cases {
+            assert!(
+                validate(template, allow_subdirs).is_err(),
+                "{template:?} was accepted"
+            );
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use crate::hook;
+use ashpd::url::Url;
+use futures_util::StreamExt;
+use std::{collections::HashMap, path::Path, time::Duration};
+use tokio::task::JoinHandle;
+use zbus::{dbus_proxy, zvariant::Value, Connection};
+
+/// How long notifications are shown, and how long we wait for their actions
+const EXPIRE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An action offered on the notification of a saved screenshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    /// Open the screenshot, invoked by clicking the notification itself
+    Open,
+    /// Show the screenshot in the file manager
+    OpenFolder,
+}
+
+impl Action {
+    const ALL: [Self; 2] = [Self::Open, Self::OpenFolder];
+
+    /// The key identifying the action in D-Bus calls and signals
+    fn key(self) -> &'static str {
+        match self {
+            Self::Open => "default",
+            Self::OpenFolder => "open-folder",
+        }
+    }
+
+    /// The label the notification server shows for the action
+    fn label(self) -> &'static str {
+        match self {
+            Self::Open => "Open",
+            Self::OpenFolder => "Open Folder",
+        }
+    }
+
+    /// The action an `ActionInvoked` signal reports for notification `id`, if
+    /// the signal is about that notification
+    fn invoked(id: u32, signal_id: u32, key: &str) -> Option<Self> {
+        if signal_id != id {
+            return None;
+        }
+        Self::ALL.into_iter().find(|action| action.key() == key)
+    }
+
+    /// Carry out the action for the screenshot at `path`
+    async fn run(self, connection: &Connection, path: &Path) {
+        match self {
+            Self::Open => hook::open_with("xdg-open", &path.to_string_lossy()),
+            Self::OpenFolder => show_in_folder(connection, path).await,
+        }
+    }
+}
+
+/// The `actions` argument of Notify: each action's key followed by its label
+fn actions(supports_actions: bool) -> Vec<&'static str> {
+    if !supports_actions {
+        return Vec::new();
+    }
+    Action::ALL
+        .into_iter()
+        .flat_map(|action| [action.key(), action.label()])
+        .collect()
+}
+
+#[dbus_proxy(assume_defaults = true)]
+trait Notifications {
+    /// Call the org.freedesktop.Notifications.Notify D-Bus method
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, &Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+
+    /// Call the org.freedesktop.Notifications.GetCapabilities D-Bus method
+    fn get_capabilities(&self) -> zbus::Result<Vec<String>>;
+
+    /// Emitted when the user invokes an action of a notification
+    #[dbus_proxy(signal)]
+    fn action_invoked(&self, id: u32, action_key: &str) -> zbus::Result<()>;
+
+    /// Emitted when a notification expires or is dismissed
+    #[dbus_proxy(signal)]
+    fn notification_closed(&self, id: u32, reason: u32) -> zbus::Result<()>;
+}
+
+/// Capabilities of notification servers that show the `image-path` hint
+const IMAGE_CAPABILITIES: [&str; 2] = ["icon-static", "body-images"];
+
+/// The hints for a notification about the screenshot at `path`
+///
+/// The screenshot is only passed as `image-path` if the server's
+/// `capabilities` show that it displays images; others show the app icon.
+fn hints(path: Option<&Path>, capabilities: &[String]) -> HashMap<&'static str, Value<'static>> {
+    let mut hints = HashMap::from([("transient", Value::Bool(true))]);
+    let shows_images = capabilities
+        .iter()
+        .any(|cap| IMAGE_CAPABILITIES.contains(&cap.as_str()));
+    if let Some(uri) = path
+        .filter(|_| shows_images)
+        .and_then(|path| Url::from_file_path(path).ok())
+    {
+        hints.insert("image-path", Value::from(String::from(uri)));
+    }
+    hints
+}
+
+/// Show a desktop notification about the screenshot
+///
+/// If `path` is given, the screenshot is shown as the notification image by
+/// servers that can display one. If the server also supports actions,
+/// clicking the notification opens the screenshot and an "Open Folder"
+/// action shows it in the file manager. Actions are only delivered while we
+/// are connected, so the returned task listens for them until the
+/// notification closes.
+pub async fn notify(
+    summary: &str,
+    body: &str,
+    path: Option<&Path>,
+) -> zbus::Result<Option<JoinHandle<()>>> {
+    let connection = Connection::session().await?;
+    let proxy = NotificationsProxy::new(&connection).await?;
+
+    let capabilities = proxy.get_capabilities().await.unwrap_or_default();
+    let supports_actions = capabilities.iter().any(|cap| cap == "actions");
+    let actions_path = path.filter(|_| supports_actions);
+    let actions = actions(actions_path.is_some());
+    let hints = hints(path, &capabilities);
+
+    // subscribe before sending so no early click is missed
+    let mut invoked = proxy.receive_action_invoked().await?;
+    let mut closed = proxy.receive_notification_closed().await?;
+
+    let id = proxy
+        .notify(
+            "COSMIC Screenshot",
+            0,
+            "com.system76.CosmicScreenshot",
+            summary,
+            body,
+            &actions,
+            hints.iter().map(|(key, value)| (*key, value)).collect(),
+            EXPIRE_TIMEOUT.as_millis() as i32,
+        )
+        .await?;
+
+    let Some(path) = actions_path else {
+        return Ok(None);
+    };
+    let path = path.to_owned();
+    let listener = tokio::spawn(async move {
+        let action = async {
+            while let Some(signal) = invoked.next().await {
+                let action = signal
+                    .args()
+                    .ok()
+                    .and_then(|args| Action::invoked(id, args.id, args.action_key));
+                if action.is_some() {
+                    return action;
+                }
+            }
+            None
+        };
+        let closed = async {
+            while let Some(signal) = closed.next().await {
+                if signal.args().is_ok_and(|args| args.id == id) {
+                    return;
+                }
+            }
+        };
+
+        let action = tokio::select! {
+            action = action => action,
+            () = closed => None,
+            () = tokio::time::sleep(EXPIRE_TIMEOUT) => None,
+        };
+        if let Some(action) = action {
+            action.run(&connection, &path).await;
+        }
+    });
+
+    Ok(Some(listener))
+}
+
+/// Show `path` selected in the file manager, or open its folder if no file
+/// manager implements org.freedesktop.FileManager1
+async fn show_in_folder(connection: &Connection, path: &Path) {
+    if let Ok(uri) = Url::from_file_path(path) {
+        let shown = connection
+            .call_method(
+                Some("org.freedesktop.FileManager1"),
+                "/org/freedesktop/FileManager1",
+                Some("org.freedesktop.FileManager1"),
+                "ShowItems",
+                &(vec![uri.as_str()], ""),
+            )
+            .await;
+        if shown.is_ok() {
+            return;
+        }
+    }
+
+    if let Some(folder) = path.parent() {
+        hook::open_with("xdg-open", &folder.to_string_lossy());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(capabilities: &[&str]) -> Vec<String> {
+        capabilities.iter().map(|cap| cap.to_string()).collect()
+    }
+
+    #[test]
+    fn image_path_hint() {
+        let path = Path::new("/tmp/Screenshot 1.png");
+        let shown = hints(Some(path), &capabilities(&["actions", "icon-static"]));
+        assert_eq!(
+            shown.get("image-path"),
+            Some(&Value::from("file:///tmp/Screenshot%201.png"))
+        );
+        assert_eq!(shown.get("transient"), Some(&Value::Bool(true)));
+
+        let shown = hints(Some(path), &capabilities(&["body-images"]));
+        assert!(shown.contains_key("image-path"));
+    }
+
+    #[test]
+    fn no_image_path_without_support_or_file() {
+        let path = Path::new("/tmp/shot.png");
+        assert!(!hints(Some(path), &capabilities(&["actions", "body"])).contains_key("image-path"));
+        assert!(!hints(None, &capabilities(&["icon-static"])).contains_key("image-path"));
+    }
+
+    #[test]
+    fn actions_array() {
+        assert_eq!(
+            actions(true),
+            ["default", "Open", "open-folder", "Open Folder"]
+        );
+        assert!(actions(false).is_empty());
+    }
+
+    #[test]
+    fn invoked_actions() {
+        assert_eq!(Action::invoked(7, 7, "default"), Some(Action::Open));
+        assert_eq!(
+            Action::invoked(7, 7, "open-folder"),
+            Some(Action::OpenFolder)
+        );
+        // other notifications and unknown keys are ignored
+        assert_eq!(Action::invoked(7, 8, "default"), None);
+        assert_eq!(Action::invoked(7, 7, "reply"), None);
+    }
+
+    #[test]
+    fn every_offered_action_maps_back() {
+        for pair in actions(true).chunks(2) {
+            assert!(Action::invoked(1, 1, pair[0]).is_some(), "{}", pair[0]);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use crate::error::Error;
+use ashpd::{
+    desktop::{screenshot::Screenshot, Request},
+    url::Url,
+    PortalError,
+};
+use std::{io, path::PathBuf, time::Duration};
+use zbus::{fdo, Connection, Proxy};
+
+/// The bus name of the desktop portal
+const DESTINATION: &str = "org.freedesktop.portal.Desktop";
+/// The object path of the desktop portal
+const PATH: &str = "/org/freedesktop/portal/desktop";
+/// The portal interface screenshots are requested from
+const INTERFACE: &str = "org.freedesktop.portal.Screenshot";
+
+/// The first Screenshot portal version that honours the `interactive` option
+const NON_INTERACTIVE_VERSION: u32 = 2;
+
+/// How many times a non-interactive screenshot request is retried after timing out
+const REQUEST_RETRIES: u32 = 2;
+/// How long to wait before retrying a screenshot request
+const REQUEST_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// The underlying D-Bus error of a portal error, if there is one
+fn zbus_error(err: &ashpd::Error) -> Option<&zbus::Error> {
+    match err {
+        ashpd::Error::Zbus(err) | ashpd::Error::Portal(PortalError::ZBus(err)) => Some(err),
+        _ => None,
+    }
+}
+
+/// Returns true if the error means the session bus or screenshot portal is not
+/// running, or the portal does not implement the Screenshot interface
+pub fn is_unavailable(err: &ashpd::Error) -> bool {
+    let Some(err) = zbus_error(err) else {
+        return false;
+    };
+    match err {
+        zbus::Error::Address(_) | zbus::Error::InterfaceNotFound => true,
+        zbus::Error::InputOutput(err) => matches!(
+            err.kind(),
+            io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+        ),
+        zbus::Error::FDO(err) => matches!(
+            **err,
+            fdo::Error::ServiceUnknown(_)
+                | fdo::Error::NameHasNoOwner(_)
+                | fdo::Error::UnknownMethod(_)
+                | fdo::Error::UnknownObject(_)
+                | fdo::Error::UnknownInterface(_)
+        ),
+        zbus::Error::MethodError(name, ..) => matches!(
+            name.as_str(),
+            "org.freedesktop.DBus.Error.ServiceUnknown"
+                | "org.freedesktop.DBus.Error.NameHasNoOwner"
+                | "org.freedesktop.DBus.Error.UnknownMethod"
+                | "org.freedesktop.DBus.Error.UnknownObject"
+                | "org.freedesktop.DBus.Error.UnknownInterface"
+        ),
+        _ => false,
+    }
+}
+
+/// Returns true if the error means the portal didn't answer in time, as can
+/// happen on a loaded system
+fn is_timeout(err: &ashpd::Error) -> bool {
+    let Some(err) = zbus_error(err) else {
+        return false;
+    };
+    match err {
+        zbus::Error::InputOutput(err) => err.kind() == io::ErrorKind::TimedOut,
+        zbus::Error::FDO(err) => matches!(
+            **err,
+            fdo::Error::NoReply(_) | fdo::Error::Timeout(_) | fdo::Error::TimedOut(_)
+        ),
+        zbus::Error::MethodError(name, ..) => matches!(
+            name.as_str(),
+            "org.freedesktop.DBus.Error.NoReply"
+                | "org.freedesktop.DBus.Error.Timeout"
+                | "org.freedesktop.DBus.Error.TimedOut"
+        ),
+        _ => false,
+    }
+}
+
+/// Returns true if a request that failed with `err` should be sent again
+///
+/// Only timeouts of non-interactive requests are retried: the portal may
+/// have received a timed out request anyway, and for an interactive one it
+/// could already be showing a dialog that a retry would open a second time.
+fn should_retry(err: &ashpd::Error, interactive: bool) -> bool {
+    !interactive && is_timeout(err)
+}
+
+/// Check that the desktop portal is running and exports the Screenshot interface
+///
+/// Without a portal backend implementing screenshots, `xdg-desktop-portal`
+/// still runs but lacks the interface, which introspection reveals before
+/// any request is made.
+pub async fn check_available() -> ashpd::Result<()> {
+    let connection = Connection::session().await?;
+    let proxy = fdo::IntrospectableProxy::builder(&connection)
+        .destination(DESTINATION)?
+        .path(PATH)?
+        .build()
+        .await?;
+    let xml = proxy.introspect().await?;
+    if xml.contains(&format!("\"{INTERFACE}\"")) {
+        Ok(())
+    } else {
+        Err(zbus::Error::InterfaceNotFound.into())
+    }
+}
+
+/// Send the screenshot request to the portal, retrying non-interactive
+/// requests that time out
+pub async fn send_request(interactive: bool, modal: bool) -> ashpd::Result<Request<Screenshot>> {
+    let mut attempt = 0;
+    loop {
+        let result = Screenshot::request()
+            .interactive(interactive)
+            .modal(modal)
+            .send()
+            .await;
+        match result {
+            Err(err) if attempt < REQUEST_RETRIES && should_retry(&err, interactive) => {
+                attempt += 1;
+                eprintln!(
+                    "screenshot portal did not answer in time, retrying ({attempt}/{REQUEST_RETRIES})"
+                );
+                tokio::time::sleep(REQUEST_RETRY_DELAY).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The local path of a screenshot the portal returned as a `file` URI
+///
+/// The path in the URI is percent-encoded, so names with spaces such as
+/// "Screenshot from …" must be decoded before the file can be opened.
+pub fn screenshot_file(uri: &Url) -> Result<PathBuf, Error> {
+    if uri.scheme() != "file" {
+        return Err(Error::UnsupportedScheme(uri.scheme().to_owned()));
+    }
+    uri.to_file_path().map_err(|()| {
+        Error::Io(
+            "invalid screenshot path",
+            io::Error::new(io::ErrorKind::InvalidInput, uri.as_str()),
+        )
+    })
+}
+
+/// The version of the Screenshot portal interface
+pub async fn version() -> zbus::Result<u32> {
+    let connection = Connection::session().await?;
+    let proxy = Proxy::new(&connection, DESTINATION, PATH, INTERFACE).await?;
+    proxy.get_property("version").await
+}
+
+/// Returns false if the portal is known to always show a dialog
+///
+/// Portals before version 2 ignore the `interactive` option. If the version
+/// can't be read, the request itself will report why.
+pub async fn supports_non_interactive() -> bool {
+    version()
+        .await
+        .map_or(true, |version| version >= NON_INTERACTIVE_VERSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use std::sync::Arc;
+    use zbus::{names::OwnedErrorName, Message};
+
+    /// A reply carrying the D-Bus error `name`, as the bus would send it
+    fn method_error(name: &str) -> ashpd::Error {
+        let message = Message::method(
+            None::<&str>,
+            Some(DESTINATION),
+            PATH,
+            Some(INTERFACE),
+            "Screenshot",
+            &(),
+        )
+        .unwrap();
+        let name = OwnedErrorName::try_from(name).unwrap();
+        zbus::Error::MethodError(name, None, Arc::new(message)).into()
+    }
+
+    fn fdo_error(err: fdo::Error) -> ashpd::Error {
+        zbus::Error::FDO(Box::new(err)).into()
+    }
+
+    #[test]
+    fn missing_portal_errors_get_the_friendly_message() {
+        let names = [
+            "org.freedesktop.DBus.Error.ServiceUnknown",
+            "org.freedesktop.DBus.Error.NameHasNoOwner",
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            "org.freedesktop.DBus.Error.UnknownObject",
+            "org.freedesktop.DBus.Error.UnknownInterface",
+        ];
+        let fdo_errors = [
+            fdo::Error::ServiceUnknown(String::new()),
+            fdo::Error::NameHasNoOwner(String::new()),
+            fdo::Error::UnknownMethod(String::new()),
+            fdo::Error::UnknownObject(String::new()),
+            fdo::Error::UnknownInterface(String::new()),
+        ];
+        let errors = names
+            .into_iter()
+            .map(method_error)
+            .chain(fdo_errors.map(fdo_error))
+            .chain([zbus::Error::InterfaceNotFound.into()]);
+        for err in errors {
+            let err = Error::request(err);
+            assert!(matches!(err, Error::PortalUnavailable(_)), "{err:?}");
+            let message = err.to_user_facing();
+            assert!(message.contains("xdg-desktop-portal"), "{message}");
+            assert!(!message.contains("org.freedesktop.DBus"), "{message}");
+        }
+    }
+
+    #[test]
+    fn other_errors_are_reported_as_is() {
+        let err = Error::request(method_error("org.freedesktop.DBus.Error.AccessDenied"));
+        assert!(matches!(err, Error::Request(_)), "{err:?}");
+        assert!(err.to_user_facing().contains("AccessDenied"));
+    }
+
+    #[test]
+    fn screenshot_files_are_decoded() {
+        let uri = Url::parse("file:///tmp/Screenshot%20from%202024-03-09%20%C3%A9.png").unwrap();
+        assert_eq!(
+            screenshot_file(&uri).unwrap(),
+            PathBuf::from("/tmp/Screenshot from 2024-03-09 é.png")
+        );
+
+        let uri = Url::parse("clipboard:").unwrap();
+        assert!(matches!(
+            screenshot_file(&uri),
+            Err(Error::UnsupportedScheme(scheme)) if scheme == "clipboard"
+        ));
+        let uri = Url::parse("file://example.com/tmp/shot.png").unwrap();
+        assert!(matches!(screenshot_file(&uri), Err(Error::Io(..))));
+    }
+
+    #[test]
+    fn non_interactive_timeouts_are_retried() {
+        let timeouts = [
+            method_error("org.freedesktop.DBus.Error.NoReply"),
+            method_error("org.freedesktop.DBus.Error.TimedOut"),
+            fdo_error(fdo::Error::Timeout(String::new())),
+            zbus::Error::InputOutput(Arc::new(io::Error::from(io::ErrorKind::TimedOut))).into(),
+        ];
+        for err in &timeouts {
+            assert!(should_retry(err, false), "{err:?}");
+            // the portal may already be showing a dialog for the first request
+            assert!(!should_retry(err, true), "{err:?}");
+        }
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let errors = [
+            method_error("org.freedesktop.DBus.Error.ServiceUnknown"),
+            fdo_error(fdo::Error::UnknownMethod(String::new())),
+            zbus::Error::InterfaceNotFound.into(),
+        ];
+        for err in &errors {
+            assert!(!should_retry(err, false), "{err:?}");
+        }
+    }
+}
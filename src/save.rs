@@ -0,0 +1,179 @@
+use crate::error::Error;
+use clap::ValueEnum;
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageFormat};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Cursor, Write},
+    path::{Path, PathBuf},
+};
+
+/// Quality used for lossy formats when none is given
+pub const DEFAULT_QUALITY: u8 = 90;
+
+/// Image formats screenshots can be saved as
+#[derive(ValueEnum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+    Bmp,
+}
+
+impl Format {
+    /// The file extension for this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+            Self::Bmp => "bmp",
+        }
+    }
+
+    /// Returns true if this format ignores the quality setting
+    ///
+    /// WebP is written with the lossless encoder, the only one available
+    /// without linking libwebp.
+    pub fn is_lossless(self) -> bool {
+        self != Self::Jpeg
+    }
+
+    /// The format matching the extension of `path`, if it is one we can save
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let format = ImageFormat::from_path(path).ok()?;
+        Self::value_variants()
+            .iter()
+            .copied()
+            .find(|variant| variant.image_format() == format)
+    }
+
+    /// The `image` crate format used to encode this format
+    pub fn image_format(self) -> ImageFormat {
+        match self {
+            Self::Png => ImageFormat::Png,
+            Self::Jpeg => ImageFormat::Jpeg,
+            Self::Webp => ImageFormat::WebP,
+            Self::Bmp => ImageFormat::Bmp,
+        }
+    }
+}
+
+/// Encode `image` in `format` and write it to `path`, returning the path written
+///
+/// `quality` ranges from 0 to 100 and only affects lossy formats. If encoding
+/// fails, the image is written uncompressed as PPM next to `path` instead, so
+/// the capture isn't lost.
+pub fn save(
+    image: &DynamicImage,
+    path: &Path,
+    format: Format,
+    quality: u8,
+) -> Result<PathBuf, Error> {
+    let err = match save_with_encoder(image, path, format, quality) {
+        Ok(()) => return Ok(path.to_owned()),
+        Err(err) => err,
+    };
+
+    let fallback = path.with_extension("ppm");
+    eprintln!(
+        "{err}, saving the screenshot uncompressed to {}",
+        fallback.display()
+    );
+    // don't leave a partially encoded file behind
+    _ = fs::remove_file(path);
+    match save_ppm(image, &fallback) {
+        Ok(()) => Ok(fallback),
+        Err(_) => Err(err),
+    }
+}
+
+/// Encode `image` in `format` and write it to `path`
+fn save_with_encoder(
+    image: &DynamicImage,
+    path: &Path,
+    format: Format,
+    quality: u8,
+) -> Result<(), Error> {
+    match format {
+        Format::Jpeg => {
+            let file =
+                File::create(path).map_err(|err| Error::Io("failed to create screenshot", err))?;
+            let encoder = JpegEncoder::new_with_quality(BufWriter::new(file), quality);
+            // JPEG has no alpha channel
+            image.to_rgb8().write_with_encoder(encoder)?;
+        }
+        _ => image.save_with_format(path, format.image_format())?,
+    }
+
+    Ok(())
+}
+
+/// Write `image` to `path` as a binary PPM, which needs no encoder
+///
+/// PPM has no alpha channel, so transparency is dropped.
+fn save_ppm(image: &DynamicImage, path: &Path) -> Result<(), Error> {
+    let image = image.to_rgb8();
+    let file = File::create(path).map_err(|err| Error::Io("failed to create screenshot", err))?;
+    let mut writer = BufWriter::new(file);
+    write!(writer, "P6\n{} {}\n255\n", image.width(), image.height())
+        .and_then(|()| writer.write_all(image.as_raw()))
+        .and_then(|()| writer.flush())
+        .map_err(|err| Error::Io("failed to write screenshot", err))
+}
+
+/// Encode `image` in `format` into memory
+///
+/// `quality` ranges from 0 to 100 and only affects lossy formats.
+pub fn encode(image: &DynamicImage, format: Format, quality: u8) -> Result<Vec<u8>, Error> {
+    let mut data = Cursor::new(Vec::new());
+    match format {
+        Format::Jpeg => {
+            let encoder = JpegEncoder::new_with_quality(&mut data, quality);
+            image.to_rgb8().write_with_encoder(encoder)?;
+        }
+        _ => image.write_to(&mut data, format.image_format())?,
+    }
+
+    Ok(data.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn jpeg_round_trip_keeps_dimensions() {
+        let image = DynamicImage::from(RgbaImage::from_pixel(33, 17, Rgba([10, 200, 30, 255])));
+        let data = encode(&image, Format::Jpeg, DEFAULT_QUALITY).unwrap();
+
+        let decoded = image::load_from_memory_with_format(&data, ImageFormat::Jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (33, 17));
+    }
+
+    #[test]
+    fn lower_jpeg_quality_gives_smaller_output() {
+        // detail for the encoder to throw away at lower quality
+        let image = DynamicImage::from(RgbaImage::from_fn(64, 64, |x, y| {
+            Rgba([(x * 4) as u8, (y * 4) as u8, ((x * y) % 256) as u8, 255])
+        }));
+        let low = encode(&image, Format::Jpeg, 20).unwrap();
+        let high = encode(&image, Format::Jpeg, 95).unwrap();
+        assert!(low.len() < high.len(), "{} >= {}", low.len(), high.len());
+    }
+
+    #[test]
+    fn quality_is_ignored_for_lossless_formats() {
+        let image = DynamicImage::from(RgbaImage::from_fn(16, 16, |x, y| {
+            Rgba([(x * 16) as u8, (y * 16) as u8, 0, 255])
+        }));
+        for format in [Format::Png, Format::Webp, Format::Bmp] {
+            assert!(format.is_lossless());
+            assert_eq!(
+                encode(&image, format, 20).unwrap(),
+                encode(&image, format, 95).unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use crate::error::Error;
+use image::ImageFormat;
+use std::{fs, net::Ipv4Addr, path::Path};
+use tiny_http::{Header, Response, Server};
+
+/// Serve the screenshot at `path` over HTTP on localhost until the process is killed
+///
+/// The server only ever binds to the loopback interface, and answers every
+/// request with the same image regardless of the requested URL.
+pub fn serve(path: &Path, port: u16) -> Result<(), Error> {
+    let data = fs::read(path).map_err(|err| Error::Io("failed to read screenshot", err))?;
+    let server = Server::http((Ipv4Addr::LOCALHOST, port)).map_err(Error::Serve)?;
+    let addr = server
+        .server_addr()
+        .to_ip()
+        .expect("HTTP server is not bound to an IP address");
+    let mime_type =
+        ImageFormat::from_path(path).map_or("image/png", |format| format.to_mime_type());
+    let content_type =
+        Header::from_bytes("Content-Type", mime_type).expect("invalid Content-Type header");
+
+    eprintln!("Serving screenshot at http://{addr}/");
+
+    for request in server.incoming_requests() {
+        let response = Response::from_data(data.clone()).with_header(content_type.clone());
+        _ = request.respond(response);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use image::DynamicImage;
+
+/// Variance of the Laplacian of the image's luma, higher for sharper images
+///
+/// Frames caught in the middle of an animation are blurred or torn, which
+/// flattens the edges the Laplacian responds to.
+pub fn sharpness(image: &DynamicImage) -> f64 {
+    let luma = image.to_luma8();
+    let (width, height) = luma.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+
+    let pixel = |x: u32, y: u32| f64::from(luma.get_pixel(x, y)[0]);
+    let mut sum = 0.0;
+    let mut sum_squares = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian = pixel(x - 1, y) + pixel(x + 1, y) + pixel(x, y - 1) + pixel(x, y + 1)
+                - 4.0 * pixel(x, y);
+            sum += laplacian;
+            sum_squares += laplacian * laplacian;
+        }
+    }
+
+    let count = f64::from((width - 2) * (height - 2));
+    let mean = sum / count;
+    sum_squares / count - mean * mean
+}
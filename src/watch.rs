@@ -0,0 +1,169 @@
+use crate::{clipboard, error::Error, post_process, Args, Saved};
+use image::ImageFormat;
+use notify::{event::ModifyKind, EventKind, RecursiveMode, Watcher};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::mpsc;
+
+/// How often a new file's size is checked while waiting for it to be written
+const STABLE_POLL: Duration = Duration::from_millis(250);
+/// How many polls to wait for a new file to stop growing before giving up
+const STABLE_POLLS: u32 = 40;
+
+/// Wait until the file at `path` stops changing size, returning false if it
+/// vanished or never settled
+async fn wait_until_written(path: &Path) -> bool {
+    let mut last_len = None;
+    for _ in 0..STABLE_POLLS {
+        let Ok(metadata) = fs::metadata(path) else {
+            return false;
+        };
+        let len = metadata.len();
+        if len > 0 && last_len == Some(len) {
+            return true;
+        }
+        last_len = Some(len);
+        tokio::time::sleep(STABLE_POLL).await;
+    }
+
+    false
+}
+
+/// The modification time of the file at `path`, if it still exists
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// The files already handled, with the modification time they were left with
+///
+/// One write can raise several events, and the on-save command may rewrite
+/// the file it is given, so a file is only handled again once it changed
+/// after it was handled.
+#[derive(Default)]
+struct Handled(HashMap<PathBuf, SystemTime>);
+
+impl Handled {
+    /// Returns true if the file at `path` exists and changed since it was handled
+    fn is_changed(&self, path: &Path) -> bool {
+        modified(path).is_some_and(|modified| self.0.get(path) != Some(&modified))
+    }
+
+    /// Record the file at `path` as handled in its current state
+    fn insert(&mut self, path: PathBuf) {
+        if let Some(modified) = modified(&path) {
+            self.0.insert(path, modified);
+        }
+    }
+}
+
+/// Give the image at `path` the same treatment as a screenshot taken by us
+async fn handle(args: &Args, path: &Path, format: ImageFormat, copy: bool) {
+    if copy {
+        match fs::read(path) {
+            Ok(data) => {
+                if let Err(err) = clipboard::copy(data, format.to_mime_type()) {
+                    eprintln!("{err}");
+                }
+            }
+            Err(err) => eprintln!("failed to read {}: {err}", path.display()),
+        }
+    }
+    if let Err(err) = post_process(args, &Saved::File(path.to_owned())).await {
+        eprintln!("{err}");
+    }
+}
+
+/// Watch `dir` and post-process every image that appears in it
+///
+/// Each new image gets the same treatment as a screenshot taken by this
+/// binary: its path is printed, the on-save command is run and a
+/// notification is sent, and with `copy` it is also put on the clipboard.
+/// A file overwritten under the same name is handled again. Failures are
+/// reported and the watch carries on until the process is killed.
+pub async fn watch(args: &Args, dir: &Path, copy: bool) -> Result<(), Error> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        _ = tx.send(event);
+    })
+    .map_err(|err| Error::Watch(err.into()))?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(|err| Error::Watch(err.into()))?;
+
+    let mut handled = Handled::default();
+    while let Some(event) = rx.recv().await {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                eprintln!("failed to watch {}: {err}", dir.display());
+                continue;
+            }
+        };
+        // files are either written in place or renamed into the directory
+        if !matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_))
+        ) {
+            continue;
+        }
+
+        for path in event.paths {
+            let Ok(format) = ImageFormat::from_path(&path) else {
+                continue;
+            };
+            if !wait_until_written(&path).await || !handled.is_changed(&path) {
+                continue;
+            }
+
+            handle(args, &path, format, copy).await;
+            // after handling, so rewrites by the on-save command aren't new files
+            handled.insert(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::env;
+
+    #[tokio::test]
+    async fn file_rewritten_by_the_hook_is_not_handled_again() {
+        let dir = env::temp_dir().join(format!("cosmic-screenshot-{}-watch", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shot.png");
+        fs::write(&path, b"screenshot").unwrap();
+
+        let args = Args::try_parse_from([
+            "cosmic-screenshot",
+            "--notify=false",
+            "--on-save",
+            "touch -d 2001-01-01 %f",
+        ])
+        .unwrap();
+        let mut handled = Handled::default();
+        assert!(handled.is_changed(&path));
+        let before = modified(&path);
+        handle(&args, &path, ImageFormat::Png, false).await;
+        handled.insert(path.clone());
+        assert_ne!(modified(&path), before, "the hook didn't touch the file");
+        assert!(!handled.is_changed(&path));
+
+        // a later change is handled again
+        fs::write(&path, b"new screenshot").unwrap();
+        assert!(handled.is_changed(&path));
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!handled.is_changed(&path));
+    }
+}
@@ -0,0 +1,149 @@
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+
+/// Width of a glyph in font pixels
+const GLYPH_WIDTH: u32 = 5;
+/// Height of a glyph in font pixels
+const GLYPH_HEIGHT: u32 = 7;
+/// Opacity of the box drawn behind the text, out of 255
+const BOX_ALPHA: u32 = 160;
+/// Screenshot height per font pixel size, so text stays legible on HiDPI
+const HEIGHT_PER_SCALE: u32 = 360;
+
+/// Corner of the screenshot the timestamp is drawn in
+#[derive(ValueEnum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+/// The rows of a 5x7 glyph, most significant bit on the left
+///
+/// Only the characters needed for timestamps are included; anything else is
+/// drawn blank.
+fn glyph(c: char) -> [u8; 7] {
+    match c {
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        ':' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+        _ => [0; 7],
+    }
+}
+
+/// Darken the pixel at `x`, `y` for the text background, if it is in bounds
+fn shade(image: &mut RgbaImage, x: u32, y: u32) {
+    if let Some(Rgba([r, g, b, a])) = image.get_pixel_mut_checked(x, y) {
+        for channel in [r, g, b] {
+            *channel = (u32::from(*channel) * (255 - BOX_ALPHA) / 255) as u8;
+        }
+        *a = (u32::from(*a) + (255 - u32::from(*a)) * BOX_ALPHA / 255) as u8;
+    }
+}
+
+/// Draw `text` in white on a translucent black box in `corner` of `image`
+///
+/// The text is scaled with the image height, and clipped if the image is too
+/// small to hold it.
+pub fn draw_text(image: &mut RgbaImage, text: &str, corner: Corner) {
+    let scale = (image.height() / HEIGHT_PER_SCALE).max(1);
+    let advance = (GLYPH_WIDTH + 1) * scale;
+    let padding = 2 * scale;
+    let margin = 4 * scale;
+
+    let chars = text.chars().count() as u32;
+    let box_width = (chars * advance).saturating_sub(scale) + 2 * padding;
+    let box_height = GLYPH_HEIGHT * scale + 2 * padding;
+    let left = match corner {
+        Corner::TopLeft | Corner::BottomLeft => margin,
+        Corner::TopRight | Corner::BottomRight => image.width().saturating_sub(box_width + margin),
+    };
+    let top = match corner {
+        Corner::TopLeft | Corner::TopRight => margin,
+        Corner::BottomLeft | Corner::BottomRight => {
+            image.height().saturating_sub(box_height + margin)
+        }
+    };
+
+    for y in top..top + box_height {
+        for x in left..left + box_width {
+            shade(image, x, y);
+        }
+    }
+
+    let white = Rgba([255, 255, 255, 255]);
+    for (i, c) in (0..).zip(text.chars()) {
+        let glyph_left = left + padding + i * advance;
+        for (row, bits) in (0..).zip(glyph(c)) {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = glyph_left + column * scale + dx;
+                        let y = top + padding + row * scale + dy;
+                        if let Some(pixel) = image.get_pixel_mut_checked(x, y) {
+                            *pixel = white;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREY: Rgba<u8> = Rgba([128, 128, 128, 255]);
+
+    #[test]
+    fn drawing_changes_pixels_but_not_dimensions() {
+        let original = RgbaImage::from_pixel(200, 100, GREY);
+        let mut image = original.clone();
+        draw_text(&mut image, "2024-03-09 14:05:07", Corner::BottomRight);
+
+        assert_eq!(image.dimensions(), original.dimensions());
+        assert_ne!(image.as_raw(), original.as_raw());
+        assert!(image
+            .pixels()
+            .any(|pixel| *pixel == Rgba([255, 255, 255, 255])));
+    }
+
+    #[test]
+    fn text_stays_in_its_corner() {
+        for (corner, x, y) in [
+            (Corner::TopLeft, 0, 0),
+            (Corner::TopRight, 150, 0),
+            (Corner::BottomLeft, 0, 50),
+            (Corner::BottomRight, 150, 50),
+        ] {
+            let mut image = RgbaImage::from_pixel(200, 100, GREY);
+            draw_text(&mut image, "12:00", corner);
+            for (px, py, pixel) in image.enumerate_pixels() {
+                let inside = (x..x + 50).contains(&px) && (y..y + 50).contains(&py);
+                assert!(inside || *pixel == GREY, "{corner:?} drew at {px},{py}");
+            }
+        }
+    }
+
+    #[test]
+    fn tiny_images_are_clipped() {
+        let mut image = RgbaImage::from_pixel(3, 2, GREY);
+        draw_text(&mut image, "2024-03-09", Corner::BottomRight);
+        assert_eq!(image.dimensions(), (3, 2));
+    }
+}